@@ -0,0 +1,147 @@
+use crate::{
+    animation_replica::{AnimationReplica, ReplicatedState},
+    animation_state_machine::StateMotion,
+    components::{animation_debug_clock, animation_overlay_ref, interpolated_translation},
+};
+use ambient_api::{
+    components::core::{
+        app::main_scene,
+        ecs::{children, parent},
+        rendering::color,
+        text::{font_size, text},
+        transform::{
+            local_to_parent, local_to_world, mesh_to_local, mesh_to_world, spherical_billboard,
+            translation,
+        },
+    },
+    concepts::make_transformable,
+    prelude::*,
+};
+
+// the nameplate floats 9 units above the player, the overlay goes right above it
+const OVERLAY_HEIGHT: f32 = 13.0; // world units above the player
+
+// transitions listed below the layer states
+const OVERLAY_HISTORY_LINES: usize = 5;
+
+/// Draws a debug overlay above every mech whose animation controller runs in animation debug
+/// mode, showing the current state, clip and time into the clip of every layer followed by the
+/// most recent transitions.
+pub fn make_animation_overlays() {
+    spawn_query(animation_debug_clock()).bind(|meshes| {
+        for (mesh_id, _) in meshes {
+            let text_id = Entity::new()
+                .with(
+                    local_to_parent(),
+                    Mat4::from_scale(Vec3::ONE * 0.02)
+                        * Mat4::from_rotation_x(-180_f32.to_radians()),
+                )
+                .with(color(), vec4(1.0, 1.0, 0.0, 1.0))
+                .with(font_size(), 24.)
+                .with(text(), String::new())
+                .with_default(main_scene())
+                .with_default(local_to_world())
+                .with_default(mesh_to_local())
+                .with_default(mesh_to_world())
+                .spawn();
+            let overlay_id = make_transformable()
+                .with_default(main_scene())
+                .with_default(local_to_world())
+                .with_default(spherical_billboard())
+                .with(children(), vec![text_id])
+                .spawn();
+            entity::add_component(text_id, parent(), overlay_id);
+            entity::add_component(mesh_id, animation_overlay_ref(), overlay_id);
+        }
+    });
+
+    despawn_query(animation_overlay_ref()).bind(|meshes| {
+        for (_, overlay_id) in meshes {
+            for text_id in entity::get_component(overlay_id, children()).unwrap_or_default() {
+                entity::despawn(text_id);
+            }
+            entity::despawn(overlay_id);
+        }
+    });
+
+    // the mech's parent is the player, which carries the position. Remote players are shown
+    // at their interpolated position instead.
+    query((animation_overlay_ref(), parent())).each_frame(|meshes| {
+        for (mesh_id, (overlay_id, player_id)) in meshes {
+            let Some(player_position) =
+                entity::get_component(player_id, interpolated_translation())
+                    .or_else(|| entity::get_component(player_id, translation()))
+            else {
+                continue;
+            };
+            entity::set_component(
+                overlay_id,
+                translation(),
+                player_position + Vec3::Z * OVERLAY_HEIGHT,
+            );
+
+            let label = overlay_text(&AnimationReplica(mesh_id));
+            for text_id in entity::get_component(overlay_id, children()).unwrap_or_default() {
+                entity::set_component(text_id, text(), label.clone());
+            }
+        }
+    });
+}
+
+fn overlay_text(replica: &AnimationReplica) -> String {
+    let mut lines = vec![];
+    let elapsed = replica.elapsed();
+    for (index, state) in replica.states().iter().enumerate() {
+        let time = elapsed.get(index).copied().unwrap_or_default();
+        lines.push(match state {
+            Ok((layer, animation_state)) => format!(
+                "{}: {} ({}) {:.2}/{:.2}s",
+                layer.name,
+                animation_state.name,
+                motion_name(&animation_state.motion),
+                animation_state.clip_time(time),
+                animation_state.duration
+            ),
+            Err(error) => error.to_string(),
+        });
+    }
+
+    let clock = replica.debug_clock().unwrap_or_default();
+    let history = replica.history();
+    for transition in &history[history.len().saturating_sub(OVERLAY_HISTORY_LINES)..] {
+        let event = match transition.event {
+            Ok(event) => format!("{event:?}"),
+            Err(encoded) => format!("event {encoded}"),
+        };
+        let layer = match transition.to {
+            Ok((layer, _)) => layer.name.as_str(),
+            Err(_) => "?",
+        };
+        lines.push(format!(
+            "{:.1}s ago {layer}: {} -{event}-> {}",
+            clock - transition.time,
+            state_name(&transition.from),
+            state_name(&transition.to)
+        ));
+    }
+    lines.join("\n")
+}
+
+fn state_name(state: &ReplicatedState) -> String {
+    match state {
+        Ok((_, animation_state)) => animation_state.name.clone(),
+        Err(error) => format!("<{error}>"),
+    }
+}
+
+/// The clip file without its path, so the overlay stays narrow
+fn motion_name(motion: &StateMotion) -> &str {
+    match motion {
+        StateMotion::Clip(clip_path) => {
+            let file = clip_path.rsplit('/').next().unwrap_or(clip_path);
+            file.strip_suffix(".anim").unwrap_or(file)
+        }
+        StateMotion::BlendSpace(_) => "blend space",
+        StateMotion::Empty => "no clip",
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{
+    animation_state_machine::{
+        state_machine, AnimationLayer, AnimationState, AnimationStateError, PlayerAnimationEvent,
+    },
+    components::{
+        animation_debug_clock, animation_history_events, animation_history_from_states,
+        animation_history_times, animation_history_to_states, player_animation_elapsed,
+        player_animation_states,
+    },
+};
+use ambient_api::prelude::*;
+
+/// A replicated state decoded with the client's state machine
+pub type ReplicatedState =
+    Result<(&'static AnimationLayer, &'static AnimationState), AnimationStateError>;
+
+/// A transition from the history the server keeps in animation debug mode
+pub struct ReplicatedTransition {
+    /// when the transition happened on the mech's `animation_debug_clock`
+    pub time: f32,
+    /// `Err` holds an encoded event this client doesn't know
+    pub event: Result<PlayerAnimationEvent, u32>,
+    pub from: ReplicatedState,
+    pub to: ReplicatedState,
+}
+
+/// Client side replica of a mech's animation states, decoded from the `player_animation_states`
+/// the server replicates. The client carries the same state machine as the server, so UI and
+/// effects can look at the layers and states directly.
+pub struct AnimationReplica(pub EntityId);
+impl AnimationReplica {
+    /// Current state of every layer, in the order of the state machine's layers. Empty until the
+    /// server replicated the states.
+    pub fn states(&self) -> Vec<ReplicatedState> {
+        let encoded = entity::get_component(self.0, player_animation_states()).unwrap_or_default();
+        state_machine().decode_layers(&encoded)
+    }
+
+    /// Seconds the mech's animation controller has been running, `None` unless the server runs
+    /// it in animation debug mode
+    pub fn debug_clock(&self) -> Option<f32> {
+        entity::get_component(self.0, animation_debug_clock())
+    }
+
+    /// Time into the current state of every layer, only replicated in animation debug mode
+    pub fn elapsed(&self) -> Vec<f32> {
+        entity::get_component(self.0, player_animation_elapsed()).unwrap_or_default()
+    }
+
+    /// The most recent transitions of every layer, oldest first. Only replicated in animation
+    /// debug mode.
+    pub fn history(&self) -> Vec<ReplicatedTransition> {
+        let times = entity::get_component(self.0, animation_history_times()).unwrap_or_default();
+        let events = entity::get_component(self.0, animation_history_events()).unwrap_or_default();
+        let from_states =
+            entity::get_component(self.0, animation_history_from_states()).unwrap_or_default();
+        let to_states =
+            entity::get_component(self.0, animation_history_to_states()).unwrap_or_default();
+
+        // the components replicate one by one, so for a frame they can disagree on the newest
+        // transition. Good enough for a debug overlay.
+        times
+            .into_iter()
+            .zip(events)
+            .zip(from_states.into_iter().zip(to_states))
+            .map(|((time, event), (from, to))| ReplicatedTransition {
+                time,
+                event: PlayerAnimationEvent::try_from(event),
+                from: state_machine().decode(from),
+                to: state_machine().decode(to),
+            })
+            .collect()
+    }
+}
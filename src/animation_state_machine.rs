@@ -0,0 +1,1229 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    sync::Mutex,
+};
+
+use serde::Deserialize;
+
+use crate::blend_space::BlendSpace;
+
+// states, clips and transitions are described in this asset, see the comment at its top. The
+// server loads it when it starts, so it can be changed without rebuilding the project.
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+pub const STATE_MACHINE_ASSET: &str = "assets/animation_state_machine.toml";
+// the config as it was at build time, used until the asset is loaded or if it is broken
+pub const STATE_MACHINE_CONFIG: &str = include_str!("../assets/animation_state_machine.toml");
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Deserialize)]
+pub enum PlayerAnimationEvent {
+    Stop,
+    Walk,
+    Dash,
+    Punch,
+    Jump,
+    Fall,
+    Land,
+    /// sent by the controller when a state's one-shot clip played to its end
+    Finished,
+}
+
+impl PlayerAnimationEvent {
+    pub const ALL: [PlayerAnimationEvent; 8] = [
+        PlayerAnimationEvent::Stop,
+        PlayerAnimationEvent::Walk,
+        PlayerAnimationEvent::Dash,
+        PlayerAnimationEvent::Punch,
+        PlayerAnimationEvent::Jump,
+        PlayerAnimationEvent::Fall,
+        PlayerAnimationEvent::Land,
+        PlayerAnimationEvent::Finished,
+    ];
+}
+
+/// Encodes an event as its index in `PlayerAnimationEvent::ALL`, for the transition history
+impl From<PlayerAnimationEvent> for u32 {
+    fn from(event: PlayerAnimationEvent) -> u32 {
+        event as u32
+    }
+}
+
+impl TryFrom<u32> for PlayerAnimationEvent {
+    /// the encoded value, which isn't the index of any event
+    type Error = u32;
+
+    fn try_from(encoded: u32) -> Result<Self, Self::Error> {
+        PlayerAnimationEvent::ALL
+            .get(encoded as usize)
+            .copied()
+            .ok_or(encoded)
+    }
+}
+
+/// Index of a state in its layer
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct PlayerAnimationState(pub u32);
+
+/// Index of a layer in the state machine
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct PlayerAnimationLayer(pub u32);
+
+impl PlayerAnimationLayer {
+    /// The first layer, which drives the whole skeleton
+    pub const BASE: PlayerAnimationLayer = PlayerAnimationLayer(0);
+}
+
+/// Version of the `u32` encoding of `AnimationStateId`, bump it whenever the layout changes
+pub const ANIMATION_STATE_ENCODING_VERSION: u32 = 1;
+
+// layout of the encoding, from the most significant bits: 8 bits version, 8 bits layer and
+// 16 bits state
+const ENCODING_VERSION_SHIFT: u32 = 24;
+const ENCODING_LAYER_SHIFT: u32 = 16;
+const MAX_LAYERS: usize = 1 << 8;
+const MAX_STATES: usize = 1 << 16;
+
+/// A state of a specific layer. This is what gets stored in `player_animation_state` and
+/// replicated to the clients in `player_animation_states`, encoded into a `u32` together with
+/// `ANIMATION_STATE_ENCODING_VERSION`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct AnimationStateId {
+    pub layer: PlayerAnimationLayer,
+    pub state: PlayerAnimationState,
+}
+
+impl From<AnimationStateId> for u32 {
+    fn from(id: AnimationStateId) -> u32 {
+        ANIMATION_STATE_ENCODING_VERSION << ENCODING_VERSION_SHIFT
+            | id.layer.0 << ENCODING_LAYER_SHIFT
+            | id.state.0
+    }
+}
+
+impl TryFrom<u32> for AnimationStateId {
+    type Error = AnimationStateError;
+
+    /// Only checks the layout of the encoding, use `AnimationStateMachine::decode` to also check
+    /// the state exists
+    fn try_from(encoded: u32) -> Result<Self, Self::Error> {
+        let version = encoded >> ENCODING_VERSION_SHIFT;
+        if version != ANIMATION_STATE_ENCODING_VERSION {
+            return Err(AnimationStateError::UnsupportedVersion(version));
+        }
+        Ok(Self {
+            layer: PlayerAnimationLayer(encoded >> ENCODING_LAYER_SHIFT & 0xff),
+            state: PlayerAnimationState(encoded & 0xffff),
+        })
+    }
+}
+
+/// Why an encoded animation state couldn't be decoded
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AnimationStateError {
+    UnsupportedVersion(u32),
+    UnknownLayer(PlayerAnimationLayer),
+    WrongLayer {
+        expected: PlayerAnimationLayer,
+        found: PlayerAnimationLayer,
+    },
+    UnknownState(AnimationStateId),
+}
+
+impl fmt::Display for AnimationStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnimationStateError::UnsupportedVersion(version) => write!(
+                f,
+                "animation state encoding version {version} is not supported, expected \
+                 {ANIMATION_STATE_ENCODING_VERSION}"
+            ),
+            AnimationStateError::UnknownLayer(layer) => {
+                write!(f, "animation layer {} is not defined", layer.0)
+            }
+            AnimationStateError::WrongLayer { expected, found } => write!(
+                f,
+                "animation state belongs to layer {} instead of layer {}",
+                found.0, expected.0
+            ),
+            AnimationStateError::UnknownState(id) => write!(
+                f,
+                "animation state {} is not defined in layer {}",
+                id.state.0, id.layer.0
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StateMachineConfig {
+    #[serde(default)]
+    default_blend: f32,
+    layers: Vec<LayerConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LayerConfig {
+    name: String,
+    initial_state: String,
+    #[serde(default)]
+    mask: Vec<String>,
+    states: Vec<StateConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StateConfig {
+    name: String,
+    #[serde(default)]
+    clip: String,
+    #[serde(default)]
+    blend_space: Option<BlendSpace>,
+    #[serde(default)]
+    looping: bool,
+    #[serde(default)]
+    duration: f32,
+    #[serde(default)]
+    transitions: HashMap<PlayerAnimationEvent, TransitionConfig>,
+    #[serde(default)]
+    notifies: Vec<AnimationNotify>,
+}
+
+/// A transition is either just the name of the target state, which uses the default blend
+/// duration, or a table with the target state and its own blend duration
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TransitionConfig {
+    Target(String),
+    Blended { to: String, blend: f32 },
+}
+
+impl TransitionConfig {
+    fn target(&self) -> &str {
+        match self {
+            TransitionConfig::Target(to) | TransitionConfig::Blended { to, .. } => to,
+        }
+    }
+
+    fn blend(&self, default_blend: f32) -> f32 {
+        match self {
+            TransitionConfig::Target(_) => default_blend,
+            TransitionConfig::Blended { blend, .. } => *blend,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StateMachineError {
+    Parse(toml::de::Error),
+    NoLayers,
+    TooManyLayers,
+    DuplicateLayer(String),
+    MaskedBaseLayer(String),
+    UnmaskedLayer(String),
+    InLayer {
+        layer: String,
+        error: Box<StateMachineError>,
+    },
+    NoStates,
+    TooManyStates,
+    DuplicateState(String),
+    UnknownInitialState(String),
+    MissingClip(String),
+    ClipAndBlendSpace(String),
+    InvalidBlendSpace {
+        state: String,
+        problem: String,
+    },
+    NegativeDefaultBlend,
+    NegativeBlend {
+        state: String,
+        event: PlayerAnimationEvent,
+    },
+    UnknownTargetState {
+        state: String,
+        event: PlayerAnimationEvent,
+        target: String,
+    },
+    MissingDuration(String),
+    NotifyOutOfRange {
+        state: String,
+        notify: String,
+    },
+    MissingTransition {
+        state: String,
+        event: PlayerAnimationEvent,
+    },
+    UnreachableState(String),
+}
+
+impl fmt::Display for StateMachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateMachineError::Parse(err) => write!(f, "invalid state machine config: {err}"),
+            StateMachineError::NoLayers => write!(f, "the state machine has no layers"),
+            StateMachineError::TooManyLayers => {
+                write!(f, "the state machine has more than {MAX_LAYERS} layers")
+            }
+            StateMachineError::DuplicateLayer(layer) => {
+                write!(f, "layer {layer:?} is defined more than once")
+            }
+            StateMachineError::MaskedBaseLayer(layer) => {
+                write!(
+                    f,
+                    "base layer {layer:?} has a mask, it always drives the whole body"
+                )
+            }
+            StateMachineError::UnmaskedLayer(layer) => {
+                write!(
+                    f,
+                    "layer {layer:?} has no mask, only the base layer may leave it out"
+                )
+            }
+            StateMachineError::InLayer { layer, error } => write!(f, "layer {layer:?}: {error}"),
+            StateMachineError::NoStates => write!(f, "the layer has no states"),
+            StateMachineError::TooManyStates => {
+                write!(f, "the layer has more than {MAX_STATES} states")
+            }
+            StateMachineError::DuplicateState(state) => {
+                write!(f, "state {state:?} is defined more than once")
+            }
+            StateMachineError::UnknownInitialState(state) => {
+                write!(f, "initial state {state:?} is not defined")
+            }
+            StateMachineError::MissingClip(state) => {
+                write!(f, "state {state:?} has neither a clip nor a blend space")
+            }
+            StateMachineError::ClipAndBlendSpace(state) => {
+                write!(f, "state {state:?} has both a clip and a blend space")
+            }
+            StateMachineError::InvalidBlendSpace { state, problem } => {
+                write!(f, "blend space of state {state:?} {problem}")
+            }
+            StateMachineError::NegativeDefaultBlend => {
+                write!(f, "the default blend duration is negative")
+            }
+            StateMachineError::NegativeBlend { state, event } => {
+                write!(
+                    f,
+                    "state {state:?} has a negative blend duration on {event:?}"
+                )
+            }
+            StateMachineError::UnknownTargetState {
+                state,
+                event,
+                target,
+            } => write!(
+                f,
+                "state {state:?} transitions to undefined state {target:?} on {event:?}"
+            ),
+            StateMachineError::MissingDuration(state) => {
+                write!(
+                    f,
+                    "state {state:?} needs a positive duration to finish or repeat its notifies"
+                )
+            }
+            StateMachineError::NotifyOutOfRange { state, notify } => {
+                write!(
+                    f,
+                    "notify {notify:?} of state {state:?} is outside of the state's duration"
+                )
+            }
+            StateMachineError::MissingTransition { state, event } => {
+                write!(f, "state {state:?} has no transition for {event:?}")
+            }
+            StateMachineError::UnreachableState(state) => {
+                write!(f, "state {state:?} can't be reached from the initial state")
+            }
+        }
+    }
+}
+
+/// Where an event leads to and how long to crossfade into the new state's clip, in seconds
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AnimationTransition {
+    pub target: PlayerAnimationState,
+    pub blend: f32,
+}
+
+/// The state an event leads to, what it plays and how long to crossfade into it in seconds.
+/// `changed` is false if the event keeps the layer in its state, which doesn't restart it.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+pub struct AnimationStep<'a> {
+    pub state: PlayerAnimationState,
+    pub animation_state: &'a AnimationState,
+    pub blend: f32,
+    pub changed: bool,
+}
+
+/// A named event fired when a state's clip plays past `time`, in seconds into the clip
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnimationNotify {
+    pub name: String,
+    pub time: f32,
+}
+
+/// What a state plays, either a single clip or a locomotion blend space. States of masked layers
+/// may play nothing, which fades the layer out so the layers below show through.
+#[derive(Debug)]
+pub enum StateMotion {
+    Clip(String),
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    BlendSpace(BlendSpace),
+    Empty,
+}
+
+#[derive(Debug)]
+pub struct AnimationState {
+    pub name: String,
+    pub motion: StateMotion,
+    pub looping: bool,
+    /// length of a one-shot state in seconds, after which the controller sends `Finished`. For
+    /// looping states this is the length of one cycle, which their notifies repeat with.
+    pub duration: f32,
+    pub notifies: Vec<AnimationNotify>,
+    transitions: HashMap<PlayerAnimationEvent, AnimationTransition>,
+}
+
+impl AnimationState {
+    /// Names of the notifies the clip plays past while its time advances from `from` to `to`.
+    /// A notify fires when its time falls into `[from, to)`, so one at 0 fires as soon as the
+    /// state is entered. Looping states fire their notifies once every cycle.
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn notifies_between(&self, from: f32, to: f32) -> impl Iterator<Item = &str> + '_ {
+        let cycle = (self.looping && self.duration > 0.0).then_some(self.duration);
+        self.notifies
+            .iter()
+            .filter(move |notify| match cycle {
+                // the first repetition of the notify at or after `from`
+                Some(cycle) => notify.time + ((from - notify.time) / cycle).ceil() * cycle < to,
+                None => from <= notify.time && notify.time < to,
+            })
+            .map(|notify| notify.name.as_str())
+    }
+
+    /// Time into the clip once the state played for `elapsed` seconds. Looping states start their
+    /// clip over every `duration`, one-shot states hold its last frame.
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub fn clip_time(&self, elapsed: f32) -> f32 {
+        if !self.looping {
+            elapsed.min(self.duration)
+        } else if self.duration > 0.0 {
+            elapsed.rem_euclid(self.duration)
+        } else {
+            elapsed
+        }
+    }
+}
+
+/// One layer of the state machine with its own states. Layers above the base layer only drive
+/// the bones in their `mask` and get blended on top of the layers below.
+#[derive(Debug)]
+pub struct AnimationLayer {
+    pub id: PlayerAnimationLayer,
+    pub name: String,
+    /// bones driven by the layer, empty for the base layer which drives the whole skeleton
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub mask: Vec<String>,
+    initial_state: PlayerAnimationState,
+    states: Vec<AnimationState>,
+}
+
+impl AnimationLayer {
+    /// Validates a layer config, adding every problem found to `errors`. Returns `None` if the
+    /// layer is too broken to build.
+    fn parse(
+        config: &LayerConfig,
+        id: PlayerAnimationLayer,
+        default_blend: f32,
+        errors: &mut Vec<StateMachineError>,
+    ) -> Option<Self> {
+        if config.states.is_empty() {
+            errors.push(StateMachineError::NoStates);
+        }
+        if config.states.len() > MAX_STATES {
+            errors.push(StateMachineError::TooManyStates);
+        }
+
+        let mut state_ids = HashMap::new();
+        for (index, state) in config.states.iter().enumerate() {
+            if state_ids
+                .insert(state.name.as_str(), PlayerAnimationState(index as u32))
+                .is_some()
+            {
+                errors.push(StateMachineError::DuplicateState(state.name.clone()));
+            }
+        }
+
+        let initial_state = state_ids.get(config.initial_state.as_str()).copied();
+        if initial_state.is_none() && !config.states.is_empty() {
+            errors.push(StateMachineError::UnknownInitialState(
+                config.initial_state.clone(),
+            ));
+        }
+
+        let mut states = vec![];
+        for state in &config.states {
+            let motion = match (&state.blend_space, state.clip.is_empty()) {
+                (None, false) => StateMotion::Clip(state.clip.clone()),
+                (Some(blend_space), true) => {
+                    for problem in blend_space.validate() {
+                        errors.push(StateMachineError::InvalidBlendSpace {
+                            state: state.name.clone(),
+                            problem,
+                        });
+                    }
+                    StateMotion::BlendSpace(blend_space.clone())
+                }
+                (None, true) => {
+                    // nothing below the base layer could show through
+                    if id == PlayerAnimationLayer::BASE {
+                        errors.push(StateMachineError::MissingClip(state.name.clone()));
+                    }
+                    StateMotion::Empty
+                }
+                (Some(_), false) => {
+                    errors.push(StateMachineError::ClipAndBlendSpace(state.name.clone()));
+                    StateMotion::Clip(state.clip.clone())
+                }
+            };
+
+            if !state.looping && state.duration <= 0.0 {
+                errors.push(StateMachineError::MissingDuration(state.name.clone()));
+            }
+            // looping notifies repeat every cycle, so the state needs to say how long one is
+            if state.looping && !state.notifies.is_empty() && state.duration <= 0.0 {
+                errors.push(StateMachineError::MissingDuration(state.name.clone()));
+            }
+            for notify in &state.notifies {
+                let in_range = if state.looping {
+                    notify.time < state.duration
+                } else {
+                    notify.time <= state.duration
+                };
+                if notify.time < 0.0 || !in_range {
+                    errors.push(StateMachineError::NotifyOutOfRange {
+                        state: state.name.clone(),
+                        notify: notify.name.clone(),
+                    });
+                }
+            }
+
+            let mut transitions = HashMap::new();
+            for event in PlayerAnimationEvent::ALL {
+                // looping states never finish, so they don't need to handle it
+                if state.looping
+                    && event == PlayerAnimationEvent::Finished
+                    && !state.transitions.contains_key(&event)
+                {
+                    let target = state_ids[state.name.as_str()];
+                    transitions.insert(event, AnimationTransition { target, blend: 0.0 });
+                    continue;
+                }
+                let Some(transition) = state.transitions.get(&event) else {
+                    errors.push(StateMachineError::MissingTransition {
+                        state: state.name.clone(),
+                        event,
+                    });
+                    continue;
+                };
+                let blend = transition.blend(default_blend);
+                if blend < 0.0 {
+                    errors.push(StateMachineError::NegativeBlend {
+                        state: state.name.clone(),
+                        event,
+                    });
+                }
+                match state_ids.get(transition.target()) {
+                    Some(&target) => {
+                        transitions.insert(event, AnimationTransition { target, blend });
+                    }
+                    None => errors.push(StateMachineError::UnknownTargetState {
+                        state: state.name.clone(),
+                        event,
+                        target: transition.target().to_string(),
+                    }),
+                }
+            }
+
+            states.push(AnimationState {
+                name: state.name.clone(),
+                motion,
+                looping: state.looping,
+                duration: state.duration,
+                notifies: state.notifies.clone(),
+                transitions,
+            });
+        }
+
+        let initial_state = initial_state?;
+        let reachable = reachable_states(initial_state, &states);
+        for (index, state) in states.iter().enumerate() {
+            if !reachable.contains(&PlayerAnimationState(index as u32)) {
+                errors.push(StateMachineError::UnreachableState(state.name.clone()));
+            }
+        }
+
+        Some(Self {
+            id,
+            name: config.name.clone(),
+            mask: config.mask.clone(),
+            initial_state,
+            states,
+        })
+    }
+
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn initial_state(&self) -> PlayerAnimationState {
+        self.initial_state
+    }
+
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn states(&self) -> &[AnimationState] {
+        &self.states
+    }
+
+    /// Looks up a state, returns `None` for ids that don't belong to this layer
+    pub fn state(&self, state: PlayerAnimationState) -> Option<&AnimationState> {
+        self.states.get(state.0 as usize)
+    }
+
+    /// The transition `event` triggers in `state`. Validation guarantees every state has a
+    /// transition for every event.
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn transition(
+        &self,
+        state: PlayerAnimationState,
+        event: PlayerAnimationEvent,
+    ) -> AnimationTransition {
+        self.states[state.0 as usize].transitions[&event]
+    }
+
+    /// Feeds `event` into the layer while it is in `state`. This is the whole transition logic
+    /// of the controller, without touching the engine.
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn step(
+        &self,
+        state: PlayerAnimationState,
+        event: PlayerAnimationEvent,
+    ) -> AnimationStep<'_> {
+        let transition = self.transition(state, event);
+        AnimationStep {
+            state: transition.target,
+            animation_state: &self.states[transition.target.0 as usize],
+            blend: transition.blend,
+            changed: transition.target != state,
+        }
+    }
+
+    /// Encodes a state of this layer for `player_animation_state`
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn encode(&self, state: PlayerAnimationState) -> u32 {
+        AnimationStateId {
+            layer: self.id,
+            state,
+        }
+        .into()
+    }
+
+    /// Decodes a state stored by `encode`, checking it is a state of this layer
+    pub fn decode(&self, encoded: u32) -> Result<PlayerAnimationState, AnimationStateError> {
+        let id = AnimationStateId::try_from(encoded)?;
+        if id.layer != self.id {
+            return Err(AnimationStateError::WrongLayer {
+                expected: self.id,
+                found: id.layer,
+            });
+        }
+        match self.state(id.state) {
+            Some(_) => Ok(id.state),
+            None => Err(AnimationStateError::UnknownState(id)),
+        }
+    }
+}
+
+/// The layers of the player animation. Every layer runs its own state and sees every event, so
+/// the legs can keep walking while an action layer punches with the upper body.
+#[derive(Debug)]
+pub struct AnimationStateMachine {
+    layers: Vec<AnimationLayer>,
+}
+
+impl AnimationStateMachine {
+    /// Parses and validates a state machine config, collecting every problem found instead of
+    /// stopping at the first one.
+    pub fn parse(source: &str) -> Result<Self, Vec<StateMachineError>> {
+        let config: StateMachineConfig =
+            toml::from_str(source).map_err(|err| vec![StateMachineError::Parse(err)])?;
+
+        let mut errors = vec![];
+        if config.layers.is_empty() {
+            errors.push(StateMachineError::NoLayers);
+        }
+        if config.layers.len() > MAX_LAYERS {
+            errors.push(StateMachineError::TooManyLayers);
+        }
+        if config.default_blend < 0.0 {
+            errors.push(StateMachineError::NegativeDefaultBlend);
+        }
+
+        let mut layer_names = HashSet::new();
+        let mut layers = vec![];
+        for (index, layer) in config.layers.iter().enumerate() {
+            if !layer_names.insert(layer.name.as_str()) {
+                errors.push(StateMachineError::DuplicateLayer(layer.name.clone()));
+            }
+            // the base layer drives the whole skeleton, every layer on top only its mask
+            let id = PlayerAnimationLayer(index as u32);
+            let is_base = id == PlayerAnimationLayer::BASE;
+            if is_base && !layer.mask.is_empty() {
+                errors.push(StateMachineError::MaskedBaseLayer(layer.name.clone()));
+            }
+            if !is_base && layer.mask.is_empty() {
+                errors.push(StateMachineError::UnmaskedLayer(layer.name.clone()));
+            }
+
+            let mut layer_errors = vec![];
+            layers.extend(AnimationLayer::parse(
+                layer,
+                id,
+                config.default_blend,
+                &mut layer_errors,
+            ));
+            errors.extend(
+                layer_errors
+                    .into_iter()
+                    .map(|error| StateMachineError::InLayer {
+                        layer: layer.name.clone(),
+                        error: Box::new(error),
+                    }),
+            );
+        }
+
+        if errors.is_empty() {
+            Ok(Self { layers })
+        } else {
+            Err(errors)
+        }
+    }
+
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn layers(&self) -> &[AnimationLayer] {
+        &self.layers
+    }
+
+    /// Looks up a layer, returns `None` for ids that don't belong to this state machine
+    pub fn layer(&self, layer: PlayerAnimationLayer) -> Option<&AnimationLayer> {
+        self.layers.get(layer.0 as usize)
+    }
+
+    /// Decodes a state of any layer, stored by `AnimationLayer::encode`
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub fn decode(
+        &self,
+        encoded: u32,
+    ) -> Result<(&AnimationLayer, &AnimationState), AnimationStateError> {
+        let id = AnimationStateId::try_from(encoded)?;
+        let layer = self
+            .layer(id.layer)
+            .ok_or(AnimationStateError::UnknownLayer(id.layer))?;
+        let state = layer.decode(encoded)?;
+        Ok((layer, layer.state(state).unwrap()))
+    }
+
+    /// Decodes the replicated states of a mech, `encoded` holds the state of every layer in the
+    /// order of the layers
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub fn decode_layers(
+        &self,
+        encoded: &[u32],
+    ) -> Vec<Result<(&AnimationLayer, &AnimationState), AnimationStateError>> {
+        encoded
+            .iter()
+            .enumerate()
+            .map(|(index, &encoded)| {
+                let layer_id = PlayerAnimationLayer(index as u32);
+                let layer = self
+                    .layer(layer_id)
+                    .ok_or(AnimationStateError::UnknownLayer(layer_id))?;
+                let state = layer.decode(encoded)?;
+                Ok((layer, layer.state(state).unwrap()))
+            })
+            .collect()
+    }
+}
+
+fn reachable_states(
+    initial_state: PlayerAnimationState,
+    states: &[AnimationState],
+) -> HashSet<PlayerAnimationState> {
+    let mut reachable = HashSet::from([initial_state]);
+    let mut queue = VecDeque::from([initial_state]);
+    while let Some(state) = queue.pop_front() {
+        for transition in states[state.0 as usize].transitions.values() {
+            if reachable.insert(transition.target) {
+                queue.push_back(transition.target);
+            }
+        }
+    }
+    reachable
+}
+
+static STATE_MACHINE: Mutex<Option<&'static AnimationStateMachine>> = Mutex::new(None);
+
+/// The player animation state machine set with `install_state_machine`, or the one bundled at
+/// build time until then. Panics with every validation error if the bundled config is invalid.
+pub fn state_machine() -> &'static AnimationStateMachine {
+    let mut state_machine = STATE_MACHINE.lock().unwrap();
+    state_machine.get_or_insert_with(|| {
+        let machine = AnimationStateMachine::parse(STATE_MACHINE_CONFIG).unwrap_or_else(|errors| {
+            for error in &errors {
+                eprintln!("animation state machine: {error}");
+            }
+            panic!("invalid animation state machine config");
+        });
+        Box::leak(Box::new(machine))
+    })
+}
+
+/// Parses and validates a state machine config and makes it the one `state_machine` returns.
+/// An invalid config leaves the current state machine in place.
+///
+/// The states of every layer are encoded by their index, so this has to happen before any of
+/// them are. Installing a new state machine leaks the old one, which may still be borrowed.
+pub fn install_state_machine(source: &str) -> Result<(), Vec<StateMachineError>> {
+    let machine = AnimationStateMachine::parse(source)?;
+    *STATE_MACHINE.lock().unwrap() = Some(Box::leak(Box::new(machine)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PlayerAnimationEvent::*;
+
+    const JUMP_CLIP: &str = "assets/mecha.glb/animations/dash_0.anim";
+    const PUNCH_CLIP: &str = "assets/mecha.glb/animations/punch_4.anim";
+
+    // a base layer with a single looping state that stays put on every event
+    const MINIMAL_CONFIG: &str = r#"
+[[layers]]
+name = "Base"
+initial_state = "Idle"
+
+[[layers.states]]
+name = "Idle"
+clip = "idle.anim"
+looping = true
+[layers.states.transitions]
+Stop = "Idle"
+Walk = "Idle"
+Dash = "Idle"
+Punch = "Idle"
+Jump = "Idle"
+Fall = "Idle"
+Land = "Idle"
+"#;
+
+    fn layer(name: &str) -> &'static AnimationLayer {
+        state_machine()
+            .layers()
+            .iter()
+            .find(|layer| layer.name == name)
+            .unwrap()
+    }
+
+    fn state_id(layer: &AnimationLayer, name: &str) -> PlayerAnimationState {
+        let index = layer.states.iter().position(|state| state.name == name);
+        PlayerAnimationState(index.unwrap() as u32)
+    }
+
+    /// Name of the state `event` leads to from the state called `from`
+    fn next(
+        layer: &'static AnimationLayer,
+        from: &str,
+        event: PlayerAnimationEvent,
+    ) -> &'static str {
+        &layer
+            .step(state_id(layer, from), event)
+            .animation_state
+            .name
+    }
+
+    fn clip(animation_state: &AnimationState) -> Option<&str> {
+        match &animation_state.motion {
+            StateMotion::Clip(clip) => Some(clip),
+            StateMotion::BlendSpace(_) | StateMotion::Empty => None,
+        }
+    }
+
+    fn parse_errors(source: &str) -> Vec<StateMachineError> {
+        AnimationStateMachine::parse(source).unwrap_err()
+    }
+
+    #[test]
+    fn invalid_config_is_not_installed() {
+        let layers = state_machine().layers().len();
+        assert!(install_state_machine("default_blend = 0.2").is_err());
+        assert_eq!(state_machine().layers().len(), layers);
+    }
+
+    #[test]
+    fn config_asset_is_valid() {
+        if let Err(errors) = AnimationStateMachine::parse(STATE_MACHINE_CONFIG) {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            panic!("invalid state machine config: {errors:#?}");
+        }
+    }
+
+    #[test]
+    fn base_layer_transition_table() {
+        let base = layer("Base");
+        #[rustfmt::skip]
+        let table = [
+            ("Locomotion", [Stop, Walk, Dash, Punch, Finished], "Locomotion"),
+            ("Locomotion", [Jump, Jump, Jump, Jump, Jump], "Jumping"),
+            ("Locomotion", [Fall, Fall, Fall, Fall, Fall], "Falling"),
+            ("Locomotion", [Land, Land, Land, Land, Land], "Landing"),
+            ("Jumping", [Stop, Punch, Jump, Finished, Finished], "Jumping"),
+            ("Jumping", [Walk, Dash, Walk, Dash, Walk], "Locomotion"),
+            ("Jumping", [Fall, Fall, Fall, Fall, Fall], "Falling"),
+            ("Jumping", [Land, Land, Land, Land, Land], "Landing"),
+            ("Falling", [Stop, Walk, Dash, Punch, Fall], "Falling"),
+            ("Falling", [Finished, Finished, Finished, Finished, Finished], "Falling"),
+            ("Falling", [Jump, Jump, Jump, Jump, Jump], "Jumping"),
+            ("Falling", [Land, Land, Land, Land, Land], "Landing"),
+            ("Landing", [Stop, Walk, Dash, Punch, Land], "Landing"),
+            ("Landing", [Jump, Jump, Jump, Jump, Jump], "Jumping"),
+            ("Landing", [Fall, Fall, Fall, Fall, Fall], "Falling"),
+            ("Landing", [Finished, Finished, Finished, Finished, Finished], "Locomotion"),
+        ];
+        for (from, events, to) in table {
+            for event in events {
+                assert_eq!(next(base, from, event), to, "{from} + {event:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn action_layer_transition_table() {
+        let action = layer("Action");
+        for event in PlayerAnimationEvent::ALL {
+            let expected = if event == Punch { "Punching" } else { "Ready" };
+            assert_eq!(next(action, "Ready", event), expected, "Ready + {event:?}");
+
+            let expected = if event == Finished {
+                "Ready"
+            } else {
+                "Punching"
+            };
+            assert_eq!(
+                next(action, "Punching", event),
+                expected,
+                "Punching + {event:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn every_state_handles_every_event() {
+        for layer in state_machine().layers() {
+            for (index, state) in layer.states.iter().enumerate() {
+                for event in PlayerAnimationEvent::ALL {
+                    let step = layer.step(PlayerAnimationState(index as u32), event);
+                    assert!(
+                        layer.state(step.state).is_some(),
+                        "{} {} + {event:?}",
+                        layer.name,
+                        state.name
+                    );
+                }
+            }
+        }
+    }
+
+    // regression: letting go of the movement keys mid air doesn't end the jump, only landing or
+    // falling does
+    #[test]
+    fn jumping_stays_jumping_on_stop() {
+        let base = layer("Base");
+        let step = base.step(state_id(base, "Jumping"), Stop);
+        assert_eq!(step.animation_state.name, "Jumping");
+        assert!(!step.changed);
+    }
+
+    // regression: punching mid air doesn't interrupt the jump, the punch plays on the action layer
+    #[test]
+    fn jumping_stays_jumping_on_punch() {
+        let base = layer("Base");
+        let step = base.step(state_id(base, "Jumping"), Punch);
+        assert_eq!(step.animation_state.name, "Jumping");
+        assert!(!step.changed);
+
+        let action = layer("Action");
+        assert_eq!(next(action, "Ready", Punch), "Punching");
+    }
+
+    #[test]
+    fn step_reports_clip_and_looping() {
+        let base = layer("Base");
+        let step = base.step(state_id(base, "Locomotion"), Jump);
+        assert!(step.changed);
+        assert_eq!(clip(step.animation_state), Some(JUMP_CLIP));
+        assert!(step.animation_state.looping);
+
+        let action = layer("Action");
+        let step = action.step(state_id(action, "Ready"), Punch);
+        assert!(step.changed);
+        assert_eq!(clip(step.animation_state), Some(PUNCH_CLIP));
+        assert!(!step.animation_state.looping);
+        assert_eq!(step.blend, 0.05);
+    }
+
+    #[test]
+    fn step_uses_default_blend() {
+        let base = layer("Base");
+        assert_eq!(base.step(state_id(base, "Locomotion"), Jump).blend, 0.2);
+    }
+
+    #[test]
+    fn locomotion_plays_blend_space() {
+        let base = layer("Base");
+        let locomotion = base.state(state_id(base, "Locomotion")).unwrap();
+        assert!(matches!(locomotion.motion, StateMotion::BlendSpace(_)));
+    }
+
+    #[test]
+    fn ready_plays_nothing() {
+        let action = layer("Action");
+        let ready = action.state(action.initial_state()).unwrap();
+        assert_eq!(ready.name, "Ready");
+        assert!(matches!(ready.motion, StateMotion::Empty));
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        for layer in state_machine().layers() {
+            for index in 0..layer.states.len() {
+                let state = PlayerAnimationState(index as u32);
+                assert_eq!(layer.decode(layer.encode(state)), Ok(state));
+            }
+        }
+    }
+
+    #[test]
+    fn events_round_trip() {
+        for event in PlayerAnimationEvent::ALL {
+            assert_eq!(PlayerAnimationEvent::try_from(u32::from(event)), Ok(event));
+        }
+        let unknown = PlayerAnimationEvent::ALL.len() as u32;
+        assert_eq!(PlayerAnimationEvent::try_from(unknown), Err(unknown));
+    }
+
+    #[test]
+    fn any_layer_decodes() {
+        let action = layer("Action");
+        let punching = state_id(action, "Punching");
+        let (layer, state) = state_machine().decode(action.encode(punching)).unwrap();
+        assert_eq!(layer.name, "Action");
+        assert_eq!(state.name, "Punching");
+
+        let unknown_layer = PlayerAnimationLayer(state_machine().layers().len() as u32);
+        let encoded = AnimationStateId {
+            layer: unknown_layer,
+            state: PlayerAnimationState(0),
+        };
+        assert!(matches!(
+            state_machine().decode(encoded.into()),
+            Err(AnimationStateError::UnknownLayer(layer)) if layer == unknown_layer
+        ));
+    }
+
+    #[test]
+    fn decoding_reports_errors() {
+        let base = layer("Base");
+        let action = layer("Action");
+        assert_eq!(
+            base.decode(0),
+            Err(AnimationStateError::UnsupportedVersion(0))
+        );
+        assert_eq!(
+            base.decode(action.encode(PlayerAnimationState(0))),
+            Err(AnimationStateError::WrongLayer {
+                expected: base.id,
+                found: action.id,
+            })
+        );
+        let unknown = PlayerAnimationState(base.states.len() as u32);
+        assert_eq!(
+            base.decode(base.encode(unknown)),
+            Err(AnimationStateError::UnknownState(AnimationStateId {
+                layer: base.id,
+                state: unknown,
+            }))
+        );
+
+        let layers = state_machine().layers();
+        let mut encoded: Vec<u32> = layers
+            .iter()
+            .map(|layer| layer.encode(layer.initial_state()))
+            .collect();
+        encoded.push(encoded[0]);
+        let decoded = state_machine().decode_layers(&encoded);
+        assert!(decoded[..layers.len()].iter().all(Result::is_ok));
+        assert!(matches!(
+            decoded[layers.len()],
+            Err(AnimationStateError::UnknownLayer(_))
+        ));
+    }
+
+    #[test]
+    fn notifies_fire_once_per_cycle() {
+        let base = layer("Base");
+        let locomotion = base.state(state_id(base, "Locomotion")).unwrap();
+        let fired = |from, to| locomotion.notifies_between(from, to).collect::<Vec<_>>();
+        assert_eq!(fired(0.0, 0.2), Vec::<&str>::new());
+        assert_eq!(fired(0.2, 0.3), ["footstep_left"]);
+        assert_eq!(fired(0.25, 0.3), ["footstep_left"]);
+        assert_eq!(fired(0.3, 0.7), Vec::<&str>::new());
+        assert_eq!(fired(0.7, 1.3), ["footstep_left", "footstep_right"]);
+        assert_eq!(fired(2.2, 2.3), ["footstep_left"]);
+    }
+
+    #[test]
+    fn notifies_at_zero_fire_on_entry() {
+        let base = layer("Base");
+        let jumping = base.state(state_id(base, "Jumping")).unwrap();
+        assert_eq!(
+            jumping.notifies_between(0.0, 0.016).collect::<Vec<_>>(),
+            ["thrusters_on"]
+        );
+    }
+
+    #[test]
+    fn one_shot_notifies_do_not_repeat() {
+        let action = layer("Action");
+        let punching = action.state(state_id(action, "Punching")).unwrap();
+        assert_eq!(
+            punching.notifies_between(0.1, 0.2).collect::<Vec<_>>(),
+            ["hit_active"]
+        );
+        assert_eq!(
+            punching.notifies_between(0.4, 10.0).collect::<Vec<_>>(),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    fn clip_time_wraps_looping_states_and_holds_one_shots() {
+        let base = layer("Base");
+        let locomotion = base.state(state_id(base, "Locomotion")).unwrap();
+        assert!((locomotion.clip_time(2.25) - 0.25).abs() < 1e-5);
+        assert_eq!(locomotion.clip_time(0.5), 0.5);
+
+        let action = layer("Action");
+        let punching = action.state(state_id(action, "Punching")).unwrap();
+        assert_eq!(punching.clip_time(0.1), 0.1);
+        assert_eq!(punching.clip_time(10.0), punching.duration);
+    }
+
+    #[test]
+    fn minimal_config_is_valid() {
+        let machine = AnimationStateMachine::parse(MINIMAL_CONFIG).unwrap();
+        let base = &machine.layers()[0];
+        // looping states don't need to handle `Finished`
+        let step = base.step(base.initial_state(), Finished);
+        assert!(!step.changed);
+    }
+
+    #[test]
+    fn missing_transition_is_reported() {
+        let errors = parse_errors(&MINIMAL_CONFIG.replace("Land = \"Idle\"\n", ""));
+        assert!(matches!(
+            &errors[..],
+            [StateMachineError::InLayer { error, .. }]
+                if matches!(**error, StateMachineError::MissingTransition { event: Land, .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_target_is_reported() {
+        let errors = parse_errors(&MINIMAL_CONFIG.replace("Jump = \"Idle\"", "Jump = \"Jumping\""));
+        assert!(matches!(
+            &errors[..],
+            [StateMachineError::InLayer { error, .. }]
+                if matches!(**error, StateMachineError::UnknownTargetState { event: Jump, .. })
+        ));
+    }
+
+    #[test]
+    fn unreachable_state_is_reported() {
+        let source = format!(
+            "{MINIMAL_CONFIG}{}",
+            MINIMAL_CONFIG
+                .split("[[layers.states]]")
+                .nth(1)
+                .map(|state| format!("[[layers.states]]{state}"))
+                .unwrap()
+                .replace("name = \"Idle\"", "name = \"Unused\"")
+        );
+        let errors = parse_errors(&source);
+        assert!(matches!(
+            &errors[..],
+            [StateMachineError::InLayer { error, .. }]
+                if matches!(&**error, StateMachineError::UnreachableState(state) if state == "Unused")
+        ));
+    }
+
+    #[test]
+    fn one_shot_state_needs_duration() {
+        let errors = parse_errors(&MINIMAL_CONFIG.replace("looping = true", "looping = false"));
+        assert!(errors.iter().any(|error| matches!(
+            error,
+            StateMachineError::InLayer { error, .. }
+                if matches!(**error, StateMachineError::MissingDuration(_))
+        )));
+    }
+
+    #[test]
+    fn base_layer_needs_motion() {
+        let errors = parse_errors(&MINIMAL_CONFIG.replace("clip = \"idle.anim\"\n", ""));
+        assert!(matches!(
+            &errors[..],
+            [StateMachineError::InLayer { error, .. }]
+                if matches!(**error, StateMachineError::MissingClip(_))
+        ));
+    }
+
+    #[test]
+    fn layer_masks_are_checked() {
+        let masked_base = MINIMAL_CONFIG.replace(
+            "initial_state = \"Idle\"",
+            "initial_state = \"Idle\"\nmask = [\"chest\"]",
+        );
+        assert!(matches!(
+            &parse_errors(&masked_base)[..],
+            [StateMachineError::MaskedBaseLayer(_)]
+        ));
+
+        let unmasked_layer = format!(
+            "{MINIMAL_CONFIG}{}",
+            MINIMAL_CONFIG.replace("name = \"Base\"", "name = \"Action\"")
+        );
+        assert!(matches!(
+            &parse_errors(&unmasked_layer)[..],
+            [StateMachineError::UnmaskedLayer(_)]
+        ));
+    }
+
+    #[test]
+    fn negative_blend_is_reported() {
+        let errors = parse_errors(
+            &MINIMAL_CONFIG.replace("Stop = \"Idle\"", "Stop = { to = \"Idle\", blend = -1.0 }"),
+        );
+        assert!(matches!(
+            &errors[..],
+            [StateMachineError::InLayer { error, .. }]
+                if matches!(**error, StateMachineError::NegativeBlend { event: Stop, .. })
+        ));
+    }
+}
@@ -0,0 +1,285 @@
+use serde::Deserialize;
+
+// limits for scaling clip playback to the ground speed, so very slow or very fast movement
+// doesn't freeze or blur the clips
+const MIN_PLAYBACK_RATE: f32 = 0.5;
+const MAX_PLAYBACK_RATE: f32 = 2.0;
+
+/// A clip in the blend space and the ground speed its feet match when played at normal rate
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlendSpaceSample {
+    pub clip: String,
+    pub speed: f32,
+}
+
+/// Locomotion blend space. The `samples` are blended along the horizontal speed of the player,
+/// the result is then blended with the strafe clips depending on the strafe direction.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlendSpace {
+    /// sorted by ascending speed
+    pub samples: Vec<BlendSpaceSample>,
+    pub strafe_left: BlendSpaceSample,
+    pub strafe_right: BlendSpaceSample,
+}
+
+/// Weights and playback rates for the blend tree of a `BlendSpace`. The speed samples form a
+/// chain of blend nodes, `((s0, s1), s2), ...`, which then gets blended with a left/right blend
+/// of the strafe clips.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BlendSpaceWeights {
+    /// one weight per blend node in the speed chain, so one less than there are samples
+    pub speed_weights: Vec<f32>,
+    pub sample_rates: Vec<f32>,
+    /// how much of the strafe clips is blended in
+    pub strafe_weight: f32,
+    /// 0 plays the left strafe clip, 1 the right one
+    pub strafe_side: f32,
+    pub strafe_rate: f32,
+    /// rate the moving clips play at overall, weighted by how much each of them is blended in.
+    /// Stationary samples don't count, so this is 0 while the blend space only plays idle.
+    pub cycle_rate: f32,
+}
+
+fn playback_rate(speed: f32, sample: &BlendSpaceSample) -> f32 {
+    if sample.speed <= 0.0 {
+        return 1.0;
+    }
+    (speed / sample.speed).clamp(MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE)
+}
+
+impl BlendSpace {
+    /// `speed` is the horizontal speed of the player in world units per second, `strafe` goes
+    /// from -1 (only moving left) over 0 (not strafing) to 1 (only moving right).
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn weights(&self, speed: f32, strafe: f32) -> BlendSpaceWeights {
+        // find the pair of samples the speed falls between, every blend node before that pair
+        // is fully blended towards its newer sample and every node after it not at all
+        let speed_weights: Vec<f32> = self
+            .samples
+            .windows(2)
+            .map(|pair| {
+                let (from, to) = (pair[0].speed, pair[1].speed);
+                ((speed - from) / (to - from)).clamp(0.0, 1.0)
+            })
+            .collect();
+        let sample_rates: Vec<f32> = self
+            .samples
+            .iter()
+            .map(|sample| playback_rate(speed, sample))
+            .collect();
+
+        let strafe = strafe.clamp(-1.0, 1.0);
+        let strafe_sample = if strafe < 0.0 {
+            &self.strafe_left
+        } else {
+            &self.strafe_right
+        };
+
+        let strafe_weight = strafe.abs();
+        let strafe_rate = playback_rate(speed, strafe_sample);
+        let speed_cycle_rate = self.speed_cycle_rate(&speed_weights, &sample_rates);
+
+        BlendSpaceWeights {
+            speed_weights,
+            sample_rates,
+            strafe_weight,
+            strafe_side: if strafe < 0.0 { 0.0 } else { 1.0 },
+            strafe_rate,
+            cycle_rate: speed_cycle_rate * (1.0 - strafe_weight) + strafe_rate * strafe_weight,
+        }
+    }
+
+    /// Weighted playback rate of the moving samples in the speed chain. A sample ends up with
+    /// the weight of the blend node fading it in times what every later node leaves of it.
+    fn speed_cycle_rate(&self, speed_weights: &[f32], sample_rates: &[f32]) -> f32 {
+        let mut cycle_rate = 0.0;
+        for (index, sample) in self.samples.iter().enumerate() {
+            if sample.speed <= 0.0 {
+                continue;
+            }
+            let fade_in = if index == 0 {
+                1.0
+            } else {
+                speed_weights[index - 1]
+            };
+            let remaining: f32 = speed_weights[index..]
+                .iter()
+                .map(|weight| 1.0 - weight)
+                .product();
+            cycle_rate += fade_in * remaining * sample_rates[index];
+        }
+        cycle_rate
+    }
+
+    /// Problems with the blend space, described for the state machine validation
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.samples.is_empty() {
+            problems.push("has no samples".to_string());
+        }
+        if self.samples.iter().any(|sample| sample.speed < 0.0) {
+            problems.push("has a sample with a negative speed".to_string());
+        }
+        if self
+            .samples
+            .windows(2)
+            .any(|pair| pair[0].speed >= pair[1].speed)
+        {
+            problems.push("samples are not sorted by strictly increasing speed".to_string());
+        }
+        if self
+            .samples
+            .iter()
+            .chain([&self.strafe_left, &self.strafe_right])
+            .any(|sample| sample.clip.is_empty())
+        {
+            problems.push("has a sample without a clip".to_string());
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn sample(clip: &str, speed: f32) -> BlendSpaceSample {
+        BlendSpaceSample {
+            clip: clip.to_string(),
+            speed,
+        }
+    }
+
+    // the same speeds as the locomotion in the state machine asset
+    fn blend_space() -> BlendSpace {
+        BlendSpace {
+            samples: vec![
+                sample("idle", 0.0),
+                sample("walk", 6.0),
+                sample("dash", 48.0),
+            ],
+            strafe_left: sample("strafe_left", 6.0),
+            strafe_right: sample("strafe_right", 6.0),
+        }
+    }
+
+    /// How much of every clip ends up in the blend, the speed samples followed by the left and
+    /// right strafe clips, the way the blend tree mixes them
+    fn clip_weights(weights: &BlendSpaceWeights) -> Vec<f32> {
+        let speed_weights = &weights.speed_weights;
+        let mut clip_weights: Vec<f32> = (0..weights.sample_rates.len())
+            .map(|index| {
+                let fade_in = if index == 0 {
+                    1.0
+                } else {
+                    speed_weights[index - 1]
+                };
+                let remaining: f32 = speed_weights[index..].iter().map(|w| 1.0 - w).product();
+                fade_in * remaining * (1.0 - weights.strafe_weight)
+            })
+            .collect();
+        clip_weights.push(weights.strafe_weight * (1.0 - weights.strafe_side));
+        clip_weights.push(weights.strafe_weight * weights.strafe_side);
+        clip_weights
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len(), "{actual:?} != {expected:?}");
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < EPSILON, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn standing_still_only_plays_idle() {
+        let weights = blend_space().weights(0.0, 0.0);
+        assert_close(&weights.speed_weights, &[0.0, 0.0]);
+        assert_close(&clip_weights(&weights), &[1.0, 0.0, 0.0, 0.0, 0.0]);
+        // idle plays at its own rate, and doesn't count as moving
+        assert_eq!(weights.sample_rates[0], 1.0);
+        assert_eq!(weights.cycle_rate, 0.0);
+    }
+
+    #[test]
+    fn speed_on_a_sample_only_plays_that_sample() {
+        let weights = blend_space().weights(6.0, 0.0);
+        assert_close(&clip_weights(&weights), &[0.0, 1.0, 0.0, 0.0, 0.0]);
+        assert_close(&weights.sample_rates, &[1.0, 1.0, MIN_PLAYBACK_RATE]);
+        assert!((weights.cycle_rate - 1.0).abs() < EPSILON);
+
+        let weights = blend_space().weights(48.0, 0.0);
+        assert_close(&clip_weights(&weights), &[0.0, 0.0, 1.0, 0.0, 0.0]);
+        assert!((weights.cycle_rate - 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn speed_between_samples_blends_them() {
+        let weights = blend_space().weights(3.0, 0.0);
+        assert_close(&weights.speed_weights, &[0.5, 0.0]);
+        assert_close(&clip_weights(&weights), &[0.5, 0.5, 0.0, 0.0, 0.0]);
+        // only the walk half is moving, at the slowest rate it may play at
+        assert!((weights.cycle_rate - 0.5 * MIN_PLAYBACK_RATE).abs() < EPSILON);
+
+        let weights = blend_space().weights(20.0, 0.0);
+        assert_close(&weights.speed_weights, &[1.0, 1.0 / 3.0]);
+        assert_close(
+            &clip_weights(&weights),
+            &[0.0, 2.0 / 3.0, 1.0 / 3.0, 0.0, 0.0],
+        );
+        // walking at over 3 times and dashing at under half the speed of their clips
+        let expected_rate = 2.0 / 3.0 * MAX_PLAYBACK_RATE + 1.0 / 3.0 * MIN_PLAYBACK_RATE;
+        assert!((weights.cycle_rate - expected_rate).abs() < EPSILON);
+    }
+
+    #[test]
+    fn speed_beyond_the_samples_stays_on_the_last_one() {
+        let weights = blend_space().weights(100.0, 0.0);
+        assert_close(&clip_weights(&weights), &[0.0, 0.0, 1.0, 0.0, 0.0]);
+        assert_close(
+            &weights.sample_rates,
+            &[1.0, MAX_PLAYBACK_RATE, MAX_PLAYBACK_RATE],
+        );
+        assert!((weights.cycle_rate - MAX_PLAYBACK_RATE).abs() < EPSILON);
+
+        // going backwards into something can't get below idle
+        let weights = blend_space().weights(-1.0, 0.0);
+        assert_close(&clip_weights(&weights), &[1.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn strafing_blends_in_the_strafe_clip_of_its_side() {
+        let weights = blend_space().weights(6.0, -1.0);
+        assert_close(&clip_weights(&weights), &[0.0, 0.0, 0.0, 1.0, 0.0]);
+        let weights = blend_space().weights(6.0, 1.0);
+        assert_close(&clip_weights(&weights), &[0.0, 0.0, 0.0, 0.0, 1.0]);
+        // strafes further than fully sideways are clamped
+        let weights = blend_space().weights(6.0, 2.0);
+        assert_close(&clip_weights(&weights), &[0.0, 0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn diagonal_strafe_splits_between_walking_and_strafing() {
+        // forward and right at once, which the server feeds in as half a strafe
+        let weights = blend_space().weights(6.0, 0.5);
+        assert_eq!(weights.strafe_side, 1.0);
+        assert_close(&clip_weights(&weights), &[0.0, 0.5, 0.0, 0.0, 0.5]);
+        assert!((weights.cycle_rate - 1.0).abs() < EPSILON);
+
+        let weights = blend_space().weights(3.0, -0.5);
+        assert_eq!(weights.strafe_side, 0.0);
+        assert_close(&clip_weights(&weights), &[0.25, 0.25, 0.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn clip_weights_sum_to_1() {
+        for speed in [-1.0, 0.0, 0.1, 3.0, 6.0, 10.0, 47.9, 48.0, 100.0] {
+            for strafe in [-2.0, -1.0, -0.5, -0.1, 0.0, 0.3, 0.5, 1.0] {
+                let weights = blend_space().weights(speed, strafe);
+                let total: f32 = clip_weights(&weights).iter().sum();
+                assert!((total - 1.0).abs() < EPSILON, "{speed} {strafe}: {total}");
+            }
+        }
+    }
+}
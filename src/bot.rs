@@ -0,0 +1,563 @@
+use serde::Deserialize;
+
+use crate::{
+    components::{bot, is_knocked_out, player_mesh_ref, player_snapshot_time},
+    input_sequence::next_sequence,
+    movement::{DASH_COOLDOWN, LOOK_SENSITIVITY},
+};
+use ambient_api::{
+    components::core::{
+        player::player,
+        transform::{rotation, translation},
+    },
+    prelude::*,
+};
+
+// behaviours of the bots when the debug config leaves them out, each bot spawned takes the next
+// one. Dozens of bots make a decent load test for the movement and animation systems.
+const BOT_BEHAVIOURS: &[BotBehaviour] = &[
+    BotBehaviour::Wander,
+    BotBehaviour::Chase,
+    BotBehaviour::Script,
+];
+
+// bots send input messages at the rate of the clients' input sender, one input command each
+const BOT_INPUT_RATE: f32 = 30.0;
+// how fast bots turn towards where they want to go, radians per second
+const BOT_TURN_SPEED: f32 = 4.0;
+
+// wandering bots pick a new heading every few seconds and now and then jump
+const WANDER_INTERVAL: f32 = 3.0; // seconds
+const WANDER_JUMP_CHANCE: f32 = 0.3; // per new heading
+
+// chasing bots dash towards players far away and punch the ones in reach
+const CHASE_DASH_DISTANCE: f32 = 30.0; // world units
+const CHASE_PUNCH_DISTANCE: f32 = 4.0; // world units
+const CHASE_PUNCH_INTERVAL: f32 = 0.5; // seconds
+
+/// What a scripted bot does for `duration` seconds
+struct ScriptStep {
+    duration: f32,
+    direction: Vec2,
+    is_dashing: bool,
+    /// radians per second, positive turns right
+    turn: f32,
+    is_jumping: bool,
+    is_punching: bool,
+}
+
+const fn step(duration: f32, direction: Vec2, turn: f32) -> ScriptStep {
+    ScriptStep {
+        duration,
+        direction,
+        is_dashing: false,
+        turn,
+        is_jumping: false,
+        is_punching: false,
+    }
+}
+
+// scripted bots loop through these steps: walk a square, then jump, punch and dash
+const BOT_SCRIPT: &[ScriptStep] = &[
+    step(2.0, Vec2::X, 0.0),
+    step(0.5, Vec2::ZERO, std::f32::consts::PI),
+    step(2.0, Vec2::X, 0.0),
+    step(0.5, Vec2::ZERO, std::f32::consts::PI),
+    step(2.0, Vec2::X, 0.0),
+    step(0.5, Vec2::ZERO, std::f32::consts::PI),
+    step(2.0, Vec2::X, 0.0),
+    step(0.5, Vec2::ZERO, std::f32::consts::PI),
+    ScriptStep {
+        is_jumping: true,
+        ..step(1.0, Vec2::ZERO, 0.0)
+    },
+    ScriptStep {
+        is_punching: true,
+        ..step(1.0, Vec2::ZERO, 0.0)
+    },
+    ScriptStep {
+        is_dashing: true,
+        ..step(0.5, Vec2::X, 0.0)
+    },
+    step(DASH_COOLDOWN, Vec2::ZERO, 0.0),
+];
+
+/// The `[bots]` section of the debug config
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotConfig {
+    /// bots spawned when the server starts
+    pub count: usize,
+    /// what the bots do, each bot spawned takes the next one
+    pub behaviours: Vec<BotBehaviour>,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            behaviours: BOT_BEHAVIOURS.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BotBehaviour {
+    /// Walk around in random directions
+    Wander,
+    /// Go after the nearest player and punch them
+    Chase,
+    /// Loop through `BOT_SCRIPT`
+    Script,
+}
+
+/// The input a bot came up with for one input message, in the shape of the messages a client
+/// sends
+pub struct BotInput {
+    pub player_id: EntityId,
+    pub input: messages::Input,
+    pub action: Option<messages::InputAction>,
+}
+
+/// One input command of a bot
+#[derive(Debug, Clone, Copy, Default)]
+struct BotCommand {
+    direction: Vec2,
+    is_dashing: bool,
+    /// radians to turn by, positive turns right
+    turn: f32,
+    is_jumping: bool,
+    is_punching: bool,
+}
+
+/// What a bot sees of its player and the others when it thinks
+#[derive(Debug, Default)]
+struct Surroundings {
+    position: Option<Vec3>,
+    /// where the player faces on the ground
+    forward: Option<Vec2>,
+    /// positions of the other players that aren't knocked out
+    targets: Vec<Vec3>,
+}
+
+impl Surroundings {
+    fn of(player_id: EntityId, player_ids: &[EntityId]) -> Self {
+        let targets = player_ids
+            .iter()
+            .filter(|&&id| id != player_id)
+            .filter(|&&id| !entity::get_component(id, is_knocked_out()).unwrap_or_default())
+            .filter_map(|&id| entity::get_component(id, translation()))
+            .collect();
+        Self {
+            position: entity::get_component(player_id, translation()),
+            forward: entity::get_component(player_id, rotation())
+                .map(|player_rotation| (player_rotation * Vec3::X).truncate()),
+            targets,
+        }
+    }
+
+    /// Radians to turn by to face `direction` on the ground
+    fn turn_towards(&self, direction: Vec2) -> f32 {
+        let Some(forward) = self.forward else {
+            return 0.0;
+        };
+        forward.angle_between(direction)
+    }
+}
+
+/// A player driven by the server instead of a client
+struct BotBrain {
+    player_id: EntityId,
+    behaviour: BotBehaviour,
+    sequence: u32,
+    since_send: f32,
+    /// seconds since the wander heading changed, the script step started or the chase punched
+    elapsed: f32,
+    /// direction a wandering bot walks in, radians from the world front
+    heading: f32,
+    script_step: usize,
+    was_dashing: bool,
+    dash_cooldown: f32,
+}
+
+/// The bots of the server. They think in the same input messages a client sends, which go
+/// through the same validation and movement as everybody else's, so to the rest of the server
+/// they are just more players.
+pub struct Bots {
+    behaviours: Vec<BotBehaviour>,
+    brains: Vec<BotBrain>,
+    /// how many bots got spawned so far, for the behaviour of the next one
+    spawned: usize,
+}
+
+impl Bots {
+    /// Spawns the bots of the `[bots]` section of the debug config. Without any behaviours there
+    /// every bot gets the next of `BOT_BEHAVIOURS`.
+    pub fn spawn(config: BotConfig) -> Self {
+        let behaviours = if config.behaviours.is_empty() {
+            BOT_BEHAVIOURS.to_vec()
+        } else {
+            config.behaviours
+        };
+        let mut bots = Self {
+            behaviours,
+            brains: Vec::new(),
+            spawned: 0,
+        };
+        bots.set_count(config.count);
+        bots
+    }
+
+    /// Spawns or despawns bots until there are `count`, the newest ones leaving first
+    pub fn set_count(&mut self, count: usize) {
+        while self.brains.len() < count {
+            let player_id = Entity::new()
+                .with_default(player())
+                .with_default(bot())
+                .spawn();
+            let behaviour = self.behaviours[self.spawned % self.behaviours.len()];
+            self.brains.push(BotBrain::new(player_id, behaviour));
+            self.spawned += 1;
+        }
+        for brain in self.brains.drain(count.min(self.brains.len())..) {
+            entity::despawn(brain.player_id);
+        }
+    }
+
+    /// Lets every bot think for `dt` seconds. Returns the input messages of the bots whose
+    /// turn it is to send one.
+    pub fn update(&mut self, dt: f32) -> Vec<BotInput> {
+        let player_ids = entity::get_all(player());
+        let interval = 1.0 / BOT_INPUT_RATE;
+        let mut inputs = Vec::new();
+        for brain in &mut self.brains {
+            brain.dash_cooldown = (brain.dash_cooldown - dt).max(0.0);
+            // the player only gets set up on the frame after spawning
+            if !entity::has_component(brain.player_id, player_mesh_ref()) {
+                continue;
+            }
+
+            brain.since_send += dt;
+            if brain.since_send < interval {
+                continue;
+            }
+            brain.since_send = (brain.since_send - interval).min(interval);
+            inputs.push(brain.think(interval, &player_ids));
+        }
+        inputs
+    }
+}
+
+impl BotBrain {
+    fn new(player_id: EntityId, behaviour: BotBehaviour) -> Self {
+        Self {
+            player_id,
+            behaviour,
+            sequence: 0,
+            since_send: 0.0,
+            elapsed: 0.0,
+            heading: 0.0,
+            script_step: 0,
+            was_dashing: false,
+            dash_cooldown: 0.0,
+        }
+    }
+
+    fn think(&mut self, dt: f32, player_ids: &[EntityId]) -> BotInput {
+        let surroundings = Surroundings::of(self.player_id, player_ids);
+        let command = self.decide(dt, &surroundings, &mut || random::<f32>());
+
+        self.sequence = next_sequence(self.sequence);
+        let max_turn = BOT_TURN_SPEED * dt;
+        // the mouse movement turning a player by as much
+        let look = vec2(
+            command.turn.clamp(-max_turn, max_turn) / LOOK_SENSITIVITY,
+            0.0,
+        );
+        let input = messages::Input::new(
+            self.sequence,
+            vec![command.direction],
+            vec![command.is_dashing],
+            vec![look],
+        );
+        let action = (command.is_jumping || command.is_punching).then(|| {
+            // bots see the world as it is, their punches aren't rewound
+            let view_time =
+                entity::get_component(self.player_id, player_snapshot_time()).unwrap_or_default();
+            messages::InputAction::new(
+                self.sequence,
+                command.is_jumping,
+                command.is_punching,
+                view_time,
+            )
+        });
+        BotInput {
+            player_id: self.player_id,
+            input,
+            action,
+        }
+    }
+
+    /// The command of the bot for the next `dt` seconds. `random` is uniformly distributed
+    /// between 0 and 1.
+    fn decide(
+        &mut self,
+        dt: f32,
+        surroundings: &Surroundings,
+        random: &mut impl FnMut() -> f32,
+    ) -> BotCommand {
+        let mut command = match self.behaviour {
+            BotBehaviour::Wander => self.wander(dt, surroundings, random),
+            BotBehaviour::Chase => self.chase(dt, surroundings),
+            BotBehaviour::Script => self.script(dt),
+        };
+
+        // a dash is a double tap of forward on the clients, keep to the cooldown the same way
+        if command.is_dashing && !self.was_dashing && self.dash_cooldown > 0.0 {
+            command.is_dashing = false;
+        }
+        if !command.is_dashing && self.was_dashing {
+            self.dash_cooldown = DASH_COOLDOWN;
+        }
+        self.was_dashing = command.is_dashing;
+        command
+    }
+
+    fn wander(
+        &mut self,
+        dt: f32,
+        surroundings: &Surroundings,
+        random: &mut impl FnMut() -> f32,
+    ) -> BotCommand {
+        self.elapsed += dt;
+        let mut is_jumping = false;
+        if self.elapsed >= WANDER_INTERVAL {
+            self.elapsed = 0.0;
+            self.heading = random() * std::f32::consts::TAU;
+            is_jumping = random() < WANDER_JUMP_CHANCE;
+        }
+        BotCommand {
+            direction: Vec2::X,
+            turn: surroundings.turn_towards(Vec2::from_angle(self.heading)),
+            is_jumping,
+            ..BotCommand::default()
+        }
+    }
+
+    fn chase(&mut self, dt: f32, surroundings: &Surroundings) -> BotCommand {
+        self.elapsed += dt;
+        let Some(position) = surroundings.position else {
+            return BotCommand::default();
+        };
+        let nearest = surroundings
+            .targets
+            .iter()
+            .min_by(|a, b| a.distance(position).total_cmp(&b.distance(position)));
+        let Some(&target) = nearest else {
+            return BotCommand::default();
+        };
+
+        let to_target = (target - position).truncate();
+        let distance = to_target.length();
+        let turn = surroundings.turn_towards(to_target);
+        let is_punching = distance <= CHASE_PUNCH_DISTANCE
+            && turn.abs() < 0.5
+            && self.elapsed >= CHASE_PUNCH_INTERVAL;
+        if is_punching {
+            self.elapsed = 0.0;
+        }
+        BotCommand {
+            direction: if distance > CHASE_PUNCH_DISTANCE {
+                Vec2::X
+            } else {
+                Vec2::ZERO
+            },
+            is_dashing: distance > CHASE_DASH_DISTANCE,
+            turn,
+            is_punching,
+            ..BotCommand::default()
+        }
+    }
+
+    fn script(&mut self, dt: f32) -> BotCommand {
+        let step = &BOT_SCRIPT[self.script_step];
+        // one-shot actions happen at the start of their step
+        let starting = self.elapsed == 0.0;
+        self.elapsed += dt;
+        if self.elapsed >= step.duration {
+            self.elapsed = 0.0;
+            self.script_step = (self.script_step + 1) % BOT_SCRIPT.len();
+        }
+        BotCommand {
+            direction: step.direction,
+            is_dashing: step.is_dashing,
+            turn: step.turn * dt,
+            is_jumping: step.is_jumping && starting,
+            is_punching: step.is_punching && starting,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / BOT_INPUT_RATE;
+
+    fn brain(behaviour: BotBehaviour) -> BotBrain {
+        BotBrain::new(EntityId::null(), behaviour)
+    }
+
+    /// Standing at the origin facing the world front, with other players at `targets`
+    fn surroundings(targets: &[Vec3]) -> Surroundings {
+        Surroundings {
+            position: Some(Vec3::ZERO),
+            forward: Some(Vec2::X),
+            targets: targets.to_vec(),
+        }
+    }
+
+    /// Lets `brain` decide for `seconds`, returning every command
+    fn decide_for(
+        brain: &mut BotBrain,
+        seconds: f32,
+        surroundings: &Surroundings,
+        random: &mut impl FnMut() -> f32,
+    ) -> Vec<BotCommand> {
+        let ticks = (seconds / DT).round() as usize;
+        (0..ticks)
+            .map(|_| {
+                brain.dash_cooldown = (brain.dash_cooldown - DT).max(0.0);
+                brain.decide(DT, surroundings, random)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wandering_bots_pick_a_new_heading_every_interval() {
+        let mut brain = brain(BotBehaviour::Wander);
+        let surroundings = surroundings(&[]);
+        let mut randoms = [0.25, 0.0, 0.5, 0.9].into_iter();
+        let mut random = || randoms.next().unwrap();
+
+        // keeps walking ahead until the interval is up, then makes a quarter turn and jumps
+        // into it
+        let commands = decide_for(&mut brain, WANDER_INTERVAL + DT, &surroundings, &mut random);
+        let (ahead, turning) =
+            commands.split_at(commands.iter().position(|c| c.is_jumping).unwrap());
+        assert!(ahead
+            .iter()
+            .all(|c| c.direction == Vec2::X && c.turn == 0.0 && !c.is_jumping));
+        assert!((ahead.len() as f32 * DT - WANDER_INTERVAL).abs() <= DT);
+        assert!((brain.heading - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+        assert!((turning[0].turn.abs() - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+
+        // turning around, without a jump
+        let commands = decide_for(&mut brain, WANDER_INTERVAL + DT, &surroundings, &mut random);
+        let command = commands.last().unwrap();
+        assert!((command.turn.abs() - std::f32::consts::PI).abs() < 1e-5);
+        assert!(commands.iter().all(|c| !c.is_jumping));
+        assert!(randoms.next().is_none());
+    }
+
+    #[test]
+    fn chasing_bots_go_after_the_nearest_player() {
+        let mut random = || unreachable!("chasing doesn't need chance");
+        let mut brain = brain(BotBehaviour::Chase);
+
+        // nobody to chase
+        let command = brain.decide(DT, &surroundings(&[]), &mut random);
+        assert_eq!(command.direction, Vec2::ZERO);
+        assert!(!command.is_dashing && !command.is_punching);
+
+        // walks to the nearest one, to the side
+        let surroundings = surroundings(&[vec3(0.0, 10.0, 0.0), vec3(-20.0, 0.0, 0.0)]);
+        let command = brain.decide(DT, &surroundings, &mut random);
+        assert_eq!(command.direction, Vec2::X);
+        assert!((command.turn.abs() - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+        assert!(!command.is_dashing && !command.is_punching);
+
+        // dashes to far away ones
+        let far = self::surroundings(&[vec3(CHASE_DASH_DISTANCE + 1.0, 0.0, 0.0)]);
+        let command = brain.decide(DT, &far, &mut random);
+        assert!(command.is_dashing);
+        assert_eq!(command.turn, 0.0);
+    }
+
+    #[test]
+    fn chasing_bots_punch_players_in_front_of_them_in_reach() {
+        let mut random = || unreachable!("chasing doesn't need chance");
+        let mut brain = brain(BotBehaviour::Chase);
+
+        // in reach but behind
+        let behind = surroundings(&[vec3(-CHASE_PUNCH_DISTANCE, 0.0, 0.0)]);
+        let commands = decide_for(&mut brain, 1.0, &behind, &mut random);
+        assert!(commands.iter().all(|c| !c.is_punching));
+        assert!(commands.iter().all(|c| c.direction == Vec2::ZERO));
+
+        // in reach in front, punching every interval
+        let in_front = surroundings(&[vec3(CHASE_PUNCH_DISTANCE, 0.0, 0.0)]);
+        let commands = decide_for(&mut brain, 2.0, &in_front, &mut random);
+        let punches = commands.iter().filter(|c| c.is_punching).count();
+        assert_eq!(punches, (2.0 / CHASE_PUNCH_INTERVAL) as usize);
+        assert!(commands[0].is_punching);
+
+        // just out of reach
+        let out_of_reach = surroundings(&[vec3(CHASE_PUNCH_DISTANCE + 0.1, 0.0, 0.0)]);
+        let commands = decide_for(&mut brain, 1.0, &out_of_reach, &mut random);
+        assert!(commands.iter().all(|c| !c.is_punching));
+        assert!(commands.iter().all(|c| c.direction == Vec2::X));
+    }
+
+    #[test]
+    fn bots_keep_to_the_dash_cooldown() {
+        let mut random = || unreachable!("chasing doesn't need chance");
+        let mut brain = brain(BotBehaviour::Chase);
+        let far = surroundings(&[vec3(CHASE_DASH_DISTANCE + 1.0, 0.0, 0.0)]);
+        let near = surroundings(&[vec3(CHASE_DASH_DISTANCE - 1.0, 0.0, 0.0)]);
+
+        assert!(brain.decide(DT, &far, &mut random).is_dashing);
+        assert!(!brain.decide(DT, &near, &mut random).is_dashing);
+        // the target getting away again doesn't restart the dash right away
+        let commands = decide_for(&mut brain, DASH_COOLDOWN + DT, &far, &mut random);
+        let refused = commands.iter().take_while(|c| !c.is_dashing).count();
+        assert_eq!(refused, (DASH_COOLDOWN / DT).round() as usize);
+        assert!(commands.last().unwrap().is_dashing);
+    }
+
+    #[test]
+    fn scripted_bots_loop_through_the_script() {
+        let mut random = || unreachable!("the script doesn't need chance");
+        let mut brain = brain(BotBehaviour::Script);
+        let surroundings = surroundings(&[]);
+        // every step can take a tick longer than it lasts, the first step of the next loop
+        // takes up the rest
+        let script_length: f32 = BOT_SCRIPT.iter().map(|step| step.duration + DT).sum();
+
+        for _ in 0..2 {
+            let commands = decide_for(&mut brain, script_length, &surroundings, &mut random);
+            // one-shot actions only once per step
+            assert_eq!(commands.iter().filter(|c| c.is_jumping).count(), 1);
+            assert_eq!(commands.iter().filter(|c| c.is_punching).count(), 1);
+            assert!(commands.iter().any(|c| c.is_dashing));
+            // walks a square, a quarter turn at every corner
+            let turned: f32 = commands.iter().map(|c| c.turn).sum();
+            assert!((turned - std::f32::consts::TAU).abs() < 1e-3, "{turned}");
+            assert_eq!(brain.script_step, 0);
+        }
+    }
+
+    #[test]
+    fn bot_behaviours_parse_from_the_debug_config() {
+        let config: BotConfig = toml::from_str("count = 3\nbehaviours = [\"chase\"]").unwrap();
+        assert_eq!(config.count, 3);
+        assert_eq!(config.behaviours, [BotBehaviour::Chase]);
+
+        let config: BotConfig = toml::from_str("").unwrap();
+        assert_eq!(config.count, 0);
+        assert_eq!(config.behaviours, BOT_BEHAVIOURS);
+
+        assert!(toml::from_str::<BotConfig>("behaviours = [\"dance\"]").is_err());
+    }
+}
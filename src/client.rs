@@ -1,3 +1,8 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
 use ambient_api::{
     components::core::{
         app::main_scene,
@@ -8,7 +13,40 @@ use ambient_api::{
     concepts::make_perspective_infinite_reverse_camera,
     prelude::*,
 };
-use components::{player_camera_ref, view_vertical_rotation};
+use components::{
+    animation_state_machine_config, bot, network_simulation_enabled, player_animation_states,
+    player_camera_ref, player_input_bindings, simulated_client_conditions, view_vertical_rotation,
+};
+
+mod animation_overlay;
+use animation_overlay::make_animation_overlays;
+
+mod animation_replica;
+use animation_replica::AnimationReplica;
+
+mod input_sequence;
+
+mod input_bindings;
+use input_bindings::{Action, InputBindings};
+
+mod input_sender;
+use input_sender::InputSender;
+
+mod interpolation;
+use interpolation::RemoteInterpolation;
+
+mod movement;
+use movement::{MovementInput, DASH_COOLDOWN};
+
+mod network_simulator;
+use network_simulator::NetworkConditions;
+
+mod prediction;
+use prediction::LocalPrediction;
+
+mod animation_state_machine;
+use animation_state_machine::install_state_machine;
+mod blend_space;
 
 #[main]
 fn main() {
@@ -70,67 +108,194 @@ fn main() {
         }
     });
 
+    // the server shares the state machine config it loaded, the replicated states only decode
+    // with the same state machine
+    run_async(async {
+        let Some(source) = entity::wait_for_component(
+            entity::synchronized_resources(),
+            animation_state_machine_config(),
+        )
+        .await
+        else {
+            return;
+        };
+        if let Err(errors) = install_state_machine(&source) {
+            for error in errors {
+                eprintln!("animation state machine of the server: {error}");
+            }
+        }
+    });
+
+    // the server and client carry the same state machine, so a state the client can't decode
+    // means they are out of sync. Report it instead of guessing what the mech plays.
+    change_query(player_animation_states())
+        .track_change(player_animation_states())
+        .bind(|meshes| {
+            for (mesh_id, _) in meshes {
+                for state in AnimationReplica(mesh_id).states() {
+                    if let Err(error) = state {
+                        eprintln!("animation replica of {mesh_id}: {error}");
+                    }
+                }
+            }
+        });
+
+    make_animation_overlays();
+
+    // the server replicates the player's own bindings once it loaded them
+    let bindings = Rc::new(RefCell::new(InputBindings::default()));
+    change_query((player(), user_id(), player_input_bindings()))
+        .track_change(player_input_bindings())
+        .bind({
+            let bindings = bindings.clone();
+            move |players| {
+                let local_user = entity::get_component(entity::resources(), local_user_id());
+                for (_, (_, user, source)) in players {
+                    if Some(user) != local_user {
+                        continue;
+                    }
+                    match InputBindings::with_overrides(&source) {
+                        Ok(player_bindings) => *bindings.borrow_mut() = player_bindings,
+                        Err(errors) => {
+                            for error in errors {
+                                eprintln!("input bindings: {error}");
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+    // the server shares the network conditions its debug config has for the clients
+    let simulated_conditions = Rc::new(Cell::new(NetworkConditions::PERFECT));
+    run_async({
+        let simulated_conditions = simulated_conditions.clone();
+        async move {
+            let Some(source) = entity::wait_for_component(
+                entity::synchronized_resources(),
+                simulated_client_conditions(),
+            )
+            .await
+            else {
+                return;
+            };
+            match toml::from_str(&source) {
+                Ok(conditions) => simulated_conditions.set(conditions),
+                Err(error) => eprintln!("network conditions of the server: {error}"),
+            }
+        }
+    });
+
+    let mut interpolation = RemoteInterpolation::default();
+    ambient_api::messages::Frame::subscribe(move |_| interpolation.update(frametime()));
+
     let mut dash_timer = 0;
     let mut is_dashing = false;
-    let mut is_jumping = false;
-    let mut is_punching = false;
+    let mut dash_cooldown = 0.0;
     let mut cursor_lock = input::CursorLockGuard::new(true);
+    let mut input_sender = InputSender::default();
+    let mut prediction = LocalPrediction::default();
     ambient_api::messages::Frame::subscribe(move |_| {
         let (delta, input) = input::get_delta();
+        let dt = frametime();
+        let simulation_enabled = entity::get_component(
+            entity::synchronized_resources(),
+            network_simulation_enabled(),
+        )
+        .unwrap_or_default();
+        input_sender.simulate(if simulation_enabled {
+            simulated_conditions.get()
+        } else {
+            NetworkConditions::PERFECT
+        });
+        // sends what the previous frames gathered, also after the cursor got unlocked so the
+        // server sees the player stop
+        input_sender.update(dt);
+
+        let (pressed, held) = {
+            let bindings = bindings.borrow();
+            (
+                bindings.actions(&delta.keys, &delta.mouse_buttons),
+                bindings.actions(&input.keys, &input.mouse_buttons),
+            )
+        };
 
         if dash_timer > 0 {
             dash_timer -= 1;
         }
-        if delta.keys.contains(&KeyCode::W) {
-            if dash_timer > 0 {
+        dash_cooldown = (dash_cooldown - dt).max(0.0);
+        if pressed.contains(&Action::MoveForward) {
+            // the server refuses dashes during the cooldown
+            if dash_timer > 0 && dash_cooldown <= 0.0 {
                 is_dashing = true;
                 dash_timer = 0;
             } else {
                 dash_timer += 50;
             }
         }
+        // the dash binding skips the double tap, but dashes forward all the same
+        if pressed.contains(&Action::Dash)
+            && held.contains(&Action::MoveForward)
+            && dash_cooldown <= 0.0
+        {
+            is_dashing = true;
+        }
 
-        if input.keys.is_empty() {
+        if held.is_empty() {
+            if is_dashing {
+                dash_cooldown = DASH_COOLDOWN;
+            }
             is_dashing = false;
         }
 
-        if delta.mouse_buttons.contains(&MouseButton::Left) {
-            is_punching = true;
-            println!("Punch!!! {:?}", is_punching);
+        if pressed.contains(&Action::ToggleNetworkSimulation) {
+            messages::NetworkSimulation::new(!simulation_enabled).send_server_reliable();
         }
-
-        if delta.keys.contains(&KeyCode::Space) {
-            is_jumping = true;
-            println!("JUMP!!! {:?}", is_jumping);
+        // the server only accepts these in debug builds
+        if pressed.contains(&Action::AddBot) || pressed.contains(&Action::RemoveBot) {
+            let bots = entity::get_all(bot()).len() as u32;
+            let count = if pressed.contains(&Action::AddBot) {
+                bots + 1
+            } else {
+                bots.saturating_sub(1)
+            };
+            messages::BotCount::new(count).send_server_reliable();
         }
 
+        // punching and jumping are one-shot requests, the server decides whether the player can
+        // actually punch or jump
+        let is_punching = pressed.contains(&Action::Punch);
+        let is_jumping = pressed.contains(&Action::Jump);
+
         if !cursor_lock.auto_unlock_on_escape(&input) {
             return;
         }
 
         let mut input_direction = Vec2::ZERO;
 
-        if input.keys.contains(&KeyCode::W) {
+        if held.contains(&Action::MoveForward) {
             input_direction.x += 1.0;
         }
-        if input.keys.contains(&KeyCode::S) {
+        if held.contains(&Action::MoveBackward) {
             input_direction.x -= 1.0;
         }
-        if input.keys.contains(&KeyCode::A) {
+        if held.contains(&Action::StrafeLeft) {
             input_direction.y -= 1.0;
         }
-        if input.keys.contains(&KeyCode::D) {
+        if held.contains(&Action::StrafeRight) {
             input_direction.y += 1.0;
         }
 
-        messages::Input::new(
-            input_direction,
+        let movement_input = MovementInput {
+            direction: input_direction,
             is_dashing,
             is_jumping,
-            is_punching,
-            delta.mouse_position.x,
-            delta.mouse_position.y,
-        )
-        .send_server_reliable();
+            // a distance already, scaling it by the frame time would turn slower at higher frame
+            // rates
+            look: delta.mouse_position,
+        };
+        let sequence = input_sender.sample(&movement_input, is_punching);
+        // move the local player right away instead of waiting for the server to do it
+        prediction.predict(movement_input, dt, sequence);
     });
 }
@@ -0,0 +1,93 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::{
+    bot::BotConfig, input_recording::InputRecordingConfig, network_simulator::NetworkConditions,
+    spawn::SpawnConfig,
+};
+
+// debug settings of the server, see the comment at the top of the asset. The server loads it
+// when it starts, so changing them only takes a restart.
+pub const DEBUG_CONFIG_ASSET: &str = "assets/debug.toml";
+
+/// The debug settings of the server, every section is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebugConfig {
+    pub input_recording: InputRecordingConfig,
+    pub network_simulator: NetworkSimulatorConfig,
+    pub bots: BotConfig,
+    pub spawn: SpawnConfig,
+}
+
+/// The `[network_simulator]` section of the debug config, see `network_simulator`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSimulatorConfig {
+    /// conditions the clients send their input messages with
+    pub client: NetworkConditions,
+    /// conditions the server receives the input messages of every client with
+    pub server: NetworkConditions,
+}
+
+#[derive(Debug)]
+pub enum DebugConfigError {
+    Load(String),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for DebugConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebugConfigError::Load(err) => write!(f, "{err}"),
+            DebugConfigError::Parse(err) => write!(f, "invalid debug config: {err}"),
+        }
+    }
+}
+
+impl DebugConfig {
+    pub fn parse(source: &str) -> Result<Self, DebugConfigError> {
+        toml::from_str(source).map_err(DebugConfigError::Parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_config_asset_is_valid() {
+        let config = DebugConfig::parse(include_str!("../assets/debug.toml")).unwrap();
+        assert!(config.input_recording.record.is_none());
+        assert_eq!(config.network_simulator.server, NetworkConditions::PERFECT);
+    }
+
+    #[test]
+    fn sections_and_settings_are_optional() {
+        let source = r#"
+[input_recording]
+replay = "session.toml"
+
+[network_simulator]
+server = { latency = 0.1, loss = 0.05 }
+"#;
+        let config = DebugConfig::parse(source).unwrap();
+        assert_eq!(
+            config.input_recording.replay.as_deref(),
+            Some("session.toml")
+        );
+        assert_eq!(
+            config.network_simulator.server,
+            NetworkConditions {
+                latency: 0.1,
+                loss: 0.05,
+                ..NetworkConditions::PERFECT
+            }
+        );
+        assert_eq!(config.network_simulator.client, NetworkConditions::PERFECT);
+
+        assert!(DebugConfig::parse("").is_ok());
+        assert!(DebugConfig::parse("[network_simulator]\nlatency = 0.1").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use crate::components::{
+    health, is_dashing, is_jumping, is_knocked_out, is_punching, knockout_timer, max_health,
+    player_input_direction, player_look, player_text_ref,
+};
+use crate::punch::Punch;
+use ambient_api::{components::core::text::text, prelude::*};
+
+pub const DEFAULT_MAX_HEALTH: f32 = 100.0;
+const RESPAWN_DELAY: f32 = 3.0; // seconds until a knocked out player respawns
+
+/// Health of a player. A player whose health drops to zero is knocked out, which freezes their
+/// input until the knockout timer runs out and they are ready to respawn.
+pub struct Health(pub EntityId);
+impl Health {
+    pub fn apply_damage(&self, amount: f32) {
+        let player_id = self.0;
+        if self.is_knocked_out() {
+            return;
+        }
+
+        let current = entity::get_component(player_id, health()).unwrap_or_default();
+        let remaining = (current - amount).max(0.0);
+        entity::set_component(player_id, health(), remaining);
+        if remaining <= 0.0 {
+            self.knock_out();
+        }
+        self.update_nameplate();
+    }
+
+    /// Knocks the player out regardless of their remaining health, e.g. after falling off the map
+    pub fn kill(&self) {
+        if self.is_knocked_out() {
+            return;
+        }
+        entity::set_component(self.0, health(), 0.0);
+        self.knock_out();
+        self.update_nameplate();
+    }
+
+    /// Counts down the knockout timer. Returns true once the timer ran out and the player should
+    /// be respawned.
+    pub fn update_knockout(&self, dt: f32) -> bool {
+        let player_id = self.0;
+        if !self.is_knocked_out() {
+            return false;
+        }
+
+        let remaining = entity::get_component(player_id, knockout_timer()).unwrap_or_default() - dt;
+        entity::set_component(player_id, knockout_timer(), remaining.max(0.0));
+        remaining <= 0.0
+    }
+
+    pub fn is_knocked_out(&self) -> bool {
+        entity::get_component(self.0, is_knocked_out()).unwrap_or_default()
+    }
+
+    pub fn update_nameplate(&self) {
+        let player_id = self.0;
+        let Some(text_id) = entity::get_component(player_id, player_text_ref()) else { return; };
+
+        let label = if self.is_knocked_out() {
+            "player KO".to_string()
+        } else {
+            let current = entity::get_component(player_id, health()).unwrap_or_default();
+            let max = entity::get_component(player_id, max_health()).unwrap_or(DEFAULT_MAX_HEALTH);
+            format!("player {:.0}/{:.0}", current.ceil(), max)
+        };
+        entity::set_component(text_id, text(), label);
+    }
+
+    fn knock_out(&self) {
+        let player_id = self.0;
+        entity::set_component(player_id, is_knocked_out(), true);
+        entity::set_component(player_id, knockout_timer(), RESPAWN_DELAY);
+
+        // drop whatever the player was doing, new input is ignored while knocked out
+        entity::set_component(player_id, player_input_direction(), Vec2::ZERO);
+        entity::set_component(player_id, player_look(), Vec2::ZERO);
+        entity::set_component(player_id, is_dashing(), false);
+        entity::set_component(player_id, is_jumping(), false);
+        entity::set_component(player_id, is_punching(), false);
+        Punch(player_id).cancel();
+    }
+
+    /// Restores full health and clears the knockout
+    pub fn revive(&self) {
+        let player_id = self.0;
+        let max = entity::get_component(player_id, max_health()).unwrap_or(DEFAULT_MAX_HEALTH);
+        entity::set_component(player_id, health(), max);
+        entity::set_component(player_id, is_knocked_out(), false);
+        entity::set_component(player_id, knockout_timer(), 0.0);
+        self.update_nameplate();
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use ambient_api::prelude::*;
+
+const DEFAULT_BINDINGS: &str = include_str!("../assets/input_bindings.toml");
+
+/// What the player can do, independent of the inputs bound to it
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    StrafeLeft,
+    StrafeRight,
+    Dash,
+    Jump,
+    Punch,
+    /// debug builds only, see `network_simulator`
+    ToggleNetworkSimulation,
+    /// debug builds only, see `bot`
+    AddBot,
+    RemoveBot,
+}
+
+impl Action {
+    const ALL: [Action; 10] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::StrafeLeft,
+        Action::StrafeRight,
+        Action::Dash,
+        Action::Jump,
+        Action::Punch,
+        Action::ToggleNetworkSimulation,
+        Action::AddBot,
+        Action::RemoveBot,
+    ];
+
+    /// Name of the action in the bindings config
+    fn name(self) -> &'static str {
+        match self {
+            Action::MoveForward => "move_forward",
+            Action::MoveBackward => "move_backward",
+            Action::StrafeLeft => "strafe_left",
+            Action::StrafeRight => "strafe_right",
+            Action::Dash => "dash",
+            Action::Jump => "jump",
+            Action::Punch => "punch",
+            Action::ToggleNetworkSimulation => "toggle_network_simulation",
+            Action::AddBot => "add_bot",
+            Action::RemoveBot => "remove_bot",
+        }
+    }
+}
+
+/// An input an action can be bound to
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+macro_rules! key_codes {
+    ($($key:ident),* $(,)?) => {
+        fn key_code(name: &str) -> Option<KeyCode> {
+            match name {
+                $(stringify!($key) => Some(KeyCode::$key),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+key_codes!(
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Key0, Key1, Key2,
+    Key3, Key4, Key5, Key6, Key7, Key8, Key9, Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5,
+    Numpad6, Numpad7, Numpad8, Numpad9, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, Up,
+    Down, Left, Right, Space, Tab, Return, Back, Insert, Delete, Home, End, PageUp, PageDown,
+    LShift, RShift, LControl, RControl, LAlt, RAlt, Comma, Period, Semicolon, Apostrophe, Slash,
+    Backslash, Minus, Equals, LBracket, RBracket, Grave,
+);
+
+impl Binding {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "MouseLeft" => Some(Binding::Mouse(MouseButton::Left)),
+            "MouseRight" => Some(Binding::Mouse(MouseButton::Right)),
+            "MouseMiddle" => Some(Binding::Mouse(MouseButton::Middle)),
+            _ => key_code(name).map(Binding::Key),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Parse(toml::de::Error),
+    UnknownAction(String),
+    UnknownInput { action: String, input: String },
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingsError::Parse(err) => write!(f, "invalid bindings config: {err}"),
+            BindingsError::UnknownAction(action) => write!(f, "there is no action {action:?}"),
+            BindingsError::UnknownInput { action, input } => {
+                write!(
+                    f,
+                    "{input:?} bound to {action} is not a key or mouse button"
+                )
+            }
+        }
+    }
+}
+
+/// Which inputs trigger which actions. Starts out with the defaults in
+/// `assets/input_bindings.toml`, and every player can bind any action differently in their own
+/// bindings, which the server loads from the assets and replicates in `player_input_bindings`.
+/// An action can have several bindings or none at all, and a binding can trigger several
+/// actions.
+pub struct InputBindings {
+    actions: HashMap<Binding, Vec<Action>>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let defaults = parse(DEFAULT_BINDINGS).unwrap_or_else(|errors| {
+            for error in &errors {
+                eprintln!("default input bindings: {error}");
+            }
+            panic!("invalid default input bindings");
+        });
+        Self::from_bindings(defaults)
+    }
+}
+
+impl InputBindings {
+    /// The default bindings, with the actions listed in `source` bound the way it says. Invalid
+    /// bindings are all reported and leave the defaults alone, rather than the player without a
+    /// way to move.
+    pub fn with_overrides(source: &str) -> Result<Self, Vec<BindingsError>> {
+        let mut bindings = parse(DEFAULT_BINDINGS).unwrap_or_default();
+        bindings.extend(parse(source)?);
+        Ok(Self::from_bindings(bindings))
+    }
+
+    fn from_bindings(bindings: HashMap<Action, Vec<Binding>>) -> Self {
+        let mut actions: HashMap<Binding, Vec<Action>> = HashMap::new();
+        for (action, action_bindings) in bindings {
+            for binding in action_bindings {
+                let binding_actions = actions.entry(binding).or_default();
+                // an input listed twice for the same action still triggers it once
+                if !binding_actions.contains(&action) {
+                    binding_actions.push(action);
+                }
+            }
+        }
+        Self { actions }
+    }
+
+    /// The actions triggered by any of `keys` or `mouse_buttons`
+    pub fn actions<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a KeyCode>,
+        mouse_buttons: impl IntoIterator<Item = &'a MouseButton>,
+    ) -> HashSet<Action> {
+        let bindings = keys.into_iter().map(|&key| Binding::Key(key)).chain(
+            mouse_buttons
+                .into_iter()
+                .map(|&button| Binding::Mouse(button)),
+        );
+        bindings
+            .filter_map(|binding| self.actions.get(&binding))
+            .flatten()
+            .copied()
+            .collect()
+    }
+}
+
+/// Parses a bindings config, collecting every problem found instead of stopping at the first
+/// one
+fn parse(source: &str) -> Result<HashMap<Action, Vec<Binding>>, Vec<BindingsError>> {
+    let config: HashMap<String, Vec<String>> =
+        toml::from_str(source).map_err(|err| vec![BindingsError::Parse(err)])?;
+
+    let mut errors = vec![];
+    let mut bindings = HashMap::new();
+    for (name, inputs) in config {
+        let Some(action) = Action::ALL.into_iter().find(|action| action.name() == name) else {
+            errors.push(BindingsError::UnknownAction(name));
+            continue;
+        };
+        let mut action_bindings = vec![];
+        for input in inputs {
+            match Binding::parse(&input) {
+                Some(binding) => action_bindings.push(binding),
+                None => errors.push(BindingsError::UnknownInput {
+                    action: name.clone(),
+                    input,
+                }),
+            }
+        }
+        bindings.insert(action, action_bindings);
+    }
+
+    if errors.is_empty() {
+        Ok(bindings)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(bindings: &InputBindings, keys: &[KeyCode]) -> HashSet<Action> {
+        bindings.actions(keys, &[] as &[MouseButton])
+    }
+
+    #[test]
+    fn default_bindings_are_valid() {
+        if let Err(errors) = parse(DEFAULT_BINDINGS) {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            panic!("invalid default bindings: {errors:#?}");
+        }
+    }
+
+    #[test]
+    fn defaults_map_keys_and_mouse_buttons() {
+        let bindings = InputBindings::default();
+        let cases: &[(&[KeyCode], &[MouseButton], &[Action])] = &[
+            (&[KeyCode::W], &[], &[Action::MoveForward]),
+            (&[KeyCode::Up], &[], &[Action::MoveForward]),
+            (
+                &[KeyCode::A, KeyCode::Space],
+                &[],
+                &[Action::StrafeLeft, Action::Jump],
+            ),
+            (&[], &[MouseButton::Left], &[Action::Punch]),
+            (&[KeyCode::P], &[MouseButton::Right], &[]),
+        ];
+        for &(keys, mouse_buttons, expected) in cases {
+            assert_eq!(
+                bindings.actions(keys, mouse_buttons),
+                expected.iter().copied().collect(),
+                "{keys:?} {mouse_buttons:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_overrides_are_all_reported() {
+        let cases: &[(&str, &[&str])] = &[
+            ("jump = [\"Space\"\n", &["invalid bindings config"]),
+            ("crouch = [\"C\"]", &["no action \"crouch\""]),
+            ("jump = [\"Spacebar\"]", &["\"Spacebar\" bound to jump"]),
+            (
+                "jump = [\"Mouse4\"]\nfly = [\"F\"]",
+                &["\"Mouse4\" bound to jump", "no action \"fly\""],
+            ),
+        ];
+        for &(source, expected) in cases {
+            let Err(errors) = InputBindings::with_overrides(source) else {
+                panic!("{source:?} should be invalid");
+            };
+            let mut errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            errors.sort();
+            let mut expected = expected.to_vec();
+            expected.sort();
+            assert_eq!(errors.len(), expected.len(), "{source:?}: {errors:?}");
+            for (error, expected) in errors.iter().zip(expected) {
+                assert!(error.contains(expected), "{source:?}: {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn duplicate_bindings() {
+        // the same input twice for one action, and one input for two actions
+        let bindings =
+            InputBindings::with_overrides("jump = [\"Space\", \"Space\"]\ndash = [\"Space\"]")
+                .unwrap();
+        assert_eq!(
+            keys(&bindings, &[KeyCode::Space]),
+            HashSet::from([Action::Jump, Action::Dash])
+        );
+        assert_eq!(bindings.actions[&Binding::Key(KeyCode::Space)].len(), 2);
+    }
+
+    #[test]
+    fn partial_overrides_keep_the_other_defaults() {
+        // AZERTY
+        let bindings =
+            InputBindings::with_overrides("move_forward = [\"Z\"]\nstrafe_left = [\"Q\"]").unwrap();
+        let cases: &[(KeyCode, &[Action])] = &[
+            (KeyCode::Z, &[Action::MoveForward]),
+            (KeyCode::Q, &[Action::StrafeLeft]),
+            // replaced, not added to
+            (KeyCode::W, &[]),
+            (KeyCode::Up, &[]),
+            // untouched defaults
+            (KeyCode::S, &[Action::MoveBackward]),
+            (KeyCode::D, &[Action::StrafeRight]),
+            (KeyCode::Space, &[Action::Jump]),
+            (KeyCode::LShift, &[Action::Dash]),
+        ];
+        for &(key, expected) in cases {
+            assert_eq!(
+                keys(&bindings, &[key]),
+                expected.iter().copied().collect(),
+                "{key:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_list_unbinds() {
+        let bindings = InputBindings::with_overrides("jump = []").unwrap();
+        assert!(keys(&bindings, &[KeyCode::Space]).is_empty());
+        assert!(InputBindings::with_overrides("").is_ok());
+    }
+}
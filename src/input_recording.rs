@@ -0,0 +1,762 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+};
+
+use crate::components::{player_animation_states, player_mesh_ref};
+use ambient_api::{
+    components::core::{
+        player::player,
+        transform::{rotation, translation},
+    },
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+// how often the recording checkpoints where the players are, which replays check against
+const RECORDING_CHECKPOINT_INTERVAL: f32 = 10.0; // seconds
+
+// how far a replayed player may end up from where the recording saw it before the replay
+// counts as diverged
+const REPLAY_TOLERANCE: f32 = 0.01; // world units
+
+/// The `[input_recording]` section of the debug config
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputRecordingConfig {
+    /// file every input the server applies gets recorded to
+    pub record: Option<String>,
+    /// recording the server replays instead of taking input from clients and bots
+    pub replay: Option<String>,
+}
+
+/// An input message the server received from a client or a bot
+pub enum ReceivedInput {
+    Input(messages::Input),
+    Action(messages::InputAction),
+}
+
+/// A recording as it is read back. The file is a TOML document the recorder keeps appending
+/// arrays of tables to, so every field collects the entries of one kind in the order they were
+/// recorded.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Recording {
+    ticks: Vec<RecordedTicks>,
+    players: Vec<RecordedPlayer>,
+    departures: Vec<RecordedDeparture>,
+    inputs: Vec<RecordedInput>,
+    checkpoints: Vec<RecordedCheckpoint>,
+}
+
+/// One entry of a recording, written on its own as an array of tables with a single element
+#[derive(Serialize)]
+enum RecordingEntry<'a> {
+    #[serde(rename = "ticks")]
+    Ticks([&'a RecordedTicks; 1]),
+    #[serde(rename = "players")]
+    Player([&'a RecordedPlayer; 1]),
+    #[serde(rename = "departures")]
+    Departure([&'a RecordedDeparture; 1]),
+    #[serde(rename = "inputs")]
+    Input([&'a RecordedInput; 1]),
+    #[serde(rename = "checkpoints")]
+    Checkpoint([&'a RecordedCheckpoint; 1]),
+}
+
+/// Ticks simulated since the previous entry of ticks
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedTicks {
+    /// seconds every tick simulated, in order
+    dt: Vec<f32>,
+}
+
+/// A player of the recorded session, referred to by the order it joined in
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedPlayer {
+    /// tick the player got simulated for the first time
+    joined: usize,
+    translation: [f32; 3],
+    rotation: [f32; 4],
+}
+
+/// A player that left, it isn't simulated from `tick` on
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedDeparture {
+    player: usize,
+    tick: usize,
+}
+
+/// An input message, applied at the start of `tick`
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedInput {
+    tick: usize,
+    player: usize,
+    sequence: u32,
+    #[serde(flatten)]
+    message: RecordedMessage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum RecordedMessage {
+    Input {
+        directions: Vec<[f32; 2]>,
+        is_dashing: Vec<bool>,
+        looks: Vec<[f32; 2]>,
+    },
+    Action {
+        is_jumping: bool,
+        is_punching: bool,
+        view_time: f32,
+    },
+}
+
+/// Where a player was before `tick`, for replays to check against
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedCheckpoint {
+    tick: usize,
+    player: usize,
+    translation: [f32; 3],
+    /// left out by recordings that only check the transforms
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    animation_states: Vec<u32>,
+}
+
+impl RecordedInput {
+    fn new(tick: usize, player: usize, input: &ReceivedInput) -> Self {
+        let (sequence, message) = match input {
+            ReceivedInput::Input(msg) => (
+                msg.sequence,
+                RecordedMessage::Input {
+                    directions: msg
+                        .input_directions
+                        .iter()
+                        .map(|direction| direction.to_array())
+                        .collect(),
+                    is_dashing: msg.is_dashing.clone(),
+                    looks: msg.looks.iter().map(|look| look.to_array()).collect(),
+                },
+            ),
+            ReceivedInput::Action(msg) => (
+                msg.sequence,
+                RecordedMessage::Action {
+                    is_jumping: msg.is_jumping,
+                    is_punching: msg.is_punching,
+                    view_time: msg.view_time,
+                },
+            ),
+        };
+        Self {
+            tick,
+            player,
+            sequence,
+            message,
+        }
+    }
+
+    fn to_received(&self) -> ReceivedInput {
+        match &self.message {
+            RecordedMessage::Input {
+                directions,
+                is_dashing,
+                looks,
+            } => ReceivedInput::Input(messages::Input::new(
+                self.sequence,
+                directions.iter().copied().map(Vec2::from_array).collect(),
+                is_dashing.clone(),
+                looks.iter().copied().map(Vec2::from_array).collect(),
+            )),
+            RecordedMessage::Action {
+                is_jumping,
+                is_punching,
+                view_time,
+            } => ReceivedInput::Action(messages::InputAction::new(
+                self.sequence,
+                *is_jumping,
+                *is_punching,
+                *view_time,
+            )),
+        }
+    }
+}
+
+fn animation_states(player_id: EntityId) -> Vec<u32> {
+    entity::get_component(player_id, player_mesh_ref())
+        .and_then(|mesh_id| entity::get_component(mesh_id, player_animation_states()))
+        .unwrap_or_default()
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    Serialize(toml::ser::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // the server is a WebAssembly module, which only gets to the files of the directories
+            // its runtime opens for it
+            RecordingError::Io(err) => write!(
+                f,
+                "{err}, the server needs access to the file system to record and replay input"
+            ),
+            RecordingError::Serialize(err) => write!(f, "could not write the recording: {err}"),
+            RecordingError::Parse(err) => write!(f, "invalid recording: {err}"),
+        }
+    }
+}
+
+/// Records every input the server applies, stamped with the tick it got applied at, together
+/// with the length of every tick and where every player spawned. Replaying that with
+/// `InputReplay` puts the server through the same session again.
+///
+/// Every tick gets appended to the file as soon as it is recorded, the server has no chance to
+/// do it once it shuts down. Every `RECORDING_CHECKPOINT_INTERVAL` seconds a checkpoint of where
+/// every player is gets appended too.
+pub struct InputRecorder {
+    path: String,
+    /// entries of the tick not appended to the file yet
+    pending: String,
+    tick: usize,
+    /// the players by the order they joined in, with whether they are still around
+    players: HashMap<EntityId, (usize, bool)>,
+    since_checkpoint: f32,
+}
+
+impl InputRecorder {
+    /// Starts a recording, replacing whatever was recorded to `path` before
+    pub fn new(path: String) -> Result<Self, RecordingError> {
+        File::create(&path).map_err(RecordingError::Io)?;
+        Ok(Self {
+            path,
+            pending: String::new(),
+            tick: 0,
+            players: HashMap::new(),
+            since_checkpoint: 0.0,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Records a tick lasting `dt` seconds, simulating `player_ids` after applying `inputs`
+    pub fn record_tick(
+        &mut self,
+        dt: f32,
+        player_ids: &[EntityId],
+        inputs: &[(EntityId, ReceivedInput)],
+    ) -> Result<(), RecordingError> {
+        // checkpointed before the tick, so the checkpoint is where the players are after the
+        // ticks recorded so far
+        if self.since_checkpoint >= RECORDING_CHECKPOINT_INTERVAL {
+            self.since_checkpoint = 0.0;
+            self.checkpoint()?;
+        }
+        self.since_checkpoint += dt;
+
+        let tick = self.tick;
+        self.tick += 1;
+        self.append(RecordingEntry::Ticks([&RecordedTicks { dt: vec![dt] }]))?;
+
+        let mut departures = vec![];
+        for (player_id, (index, present)) in &mut self.players {
+            if *present && !player_ids.contains(player_id) {
+                *present = false;
+                departures.push(RecordedDeparture {
+                    player: *index,
+                    tick,
+                });
+            }
+        }
+        for departure in &departures {
+            self.append(RecordingEntry::Departure([departure]))?;
+        }
+        for (player_id, input) in inputs {
+            let index = self.player(*player_id, tick)?;
+            self.append(RecordingEntry::Input([&RecordedInput::new(
+                tick, index, input,
+            )]))?;
+        }
+        for &player_id in player_ids {
+            self.player(player_id, tick)?;
+        }
+        self.flush()
+    }
+
+    /// Index of a player in the recording, recording it as joining at `tick` if it is new
+    fn player(&mut self, player_id: EntityId, tick: usize) -> Result<usize, RecordingError> {
+        if let Some(&(index, _)) = self.players.get(&player_id) {
+            return Ok(index);
+        }
+        let index = self.players.len();
+        self.players.insert(player_id, (index, true));
+        self.append(RecordingEntry::Player([&RecordedPlayer {
+            joined: tick,
+            translation: entity::get_component(player_id, translation())
+                .unwrap_or_default()
+                .to_array(),
+            rotation: entity::get_component(player_id, rotation())
+                .unwrap_or_default()
+                .to_array(),
+        }]))?;
+        Ok(index)
+    }
+
+    fn append(&mut self, entry: RecordingEntry) -> Result<(), RecordingError> {
+        let source = toml::to_string(&entry).map_err(RecordingError::Serialize)?;
+        self.pending.push_str(&source);
+        self.pending.push('\n');
+        Ok(())
+    }
+
+    /// Records where every player is before the next tick
+    fn checkpoint(&mut self) -> Result<(), RecordingError> {
+        let mut checkpoints: Vec<RecordedCheckpoint> = self
+            .players
+            .iter()
+            .filter(|(_, &(_, present))| present)
+            .map(|(&player_id, &(index, _))| RecordedCheckpoint {
+                tick: self.tick,
+                player: index,
+                translation: entity::get_component(player_id, translation())
+                    .unwrap_or_default()
+                    .to_array(),
+                animation_states: animation_states(player_id),
+            })
+            .collect();
+        checkpoints.sort_by_key(|checkpoint| checkpoint.player);
+        for checkpoint in &checkpoints {
+            self.append(RecordingEntry::Checkpoint([checkpoint]))?;
+        }
+        Ok(())
+    }
+
+    /// Appends the entries recorded since the last flush to the file
+    fn flush(&mut self) -> Result<(), RecordingError> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(RecordingError::Io)?;
+        file.write_all(self.pending.as_bytes())
+            .map_err(RecordingError::Io)?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// A replayed player that didn't end up where the recording saw it
+#[derive(Debug)]
+pub struct ReplayDivergence {
+    pub tick: usize,
+    pub player: usize,
+    pub expected_translation: Vec3,
+    pub translation: Vec3,
+    pub expected_animation_states: Vec<u32>,
+    pub animation_states: Vec<u32>,
+}
+
+impl fmt::Display for ReplayDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "player {} before tick {} was at {} instead of {}",
+            self.player, self.tick, self.translation, self.expected_translation
+        )?;
+        if self.animation_states != self.expected_animation_states {
+            write!(
+                f,
+                " in animation states {:?} instead of {:?}",
+                self.animation_states, self.expected_animation_states
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What the server does on the next tick of a replay
+pub enum ReplayStep {
+    /// Wait for the players joining on the next tick to be set up
+    Waiting,
+    /// Simulate a tick lasting `dt` seconds after applying `inputs`
+    Tick {
+        dt: f32,
+        inputs: Vec<(EntityId, ReceivedInput)>,
+    },
+    /// The recording is over after `ticks` ticks, with the players ending up somewhere else
+    /// than in the recording at every one of `divergences`
+    Finished {
+        ticks: usize,
+        divergences: Vec<ReplayDivergence>,
+    },
+}
+
+/// Replays a recording of `InputRecorder`. The recorded players are stood in for by players
+/// the replay spawns when they joined, put where they spawned and fed their recorded input at
+/// the ticks it was applied at, with every tick lasting as long as it did. Given the same
+/// input the server simulates the same session, which the replay checks at every checkpoint of
+/// the recording.
+///
+/// Clients connecting to watch the replay have their own input ignored.
+pub struct InputReplay {
+    recording: Recording,
+    ticks: Vec<f32>,
+    tick: usize,
+    next_input: usize,
+    next_checkpoint: usize,
+    /// stand-ins of the recorded players, by their index in the recording
+    players: HashMap<usize, EntityId>,
+    divergences: Vec<ReplayDivergence>,
+}
+
+impl InputReplay {
+    pub fn load(path: &str) -> Result<Self, RecordingError> {
+        let source = std::fs::read_to_string(path).map_err(RecordingError::Io)?;
+        Self::parse(&source)
+    }
+
+    fn parse(source: &str) -> Result<Self, RecordingError> {
+        let mut recording: Recording = toml::from_str(source).map_err(RecordingError::Parse)?;
+        recording
+            .checkpoints
+            .sort_by_key(|checkpoint| checkpoint.tick);
+        Ok(Self {
+            ticks: recording
+                .ticks
+                .iter()
+                .flat_map(|ticks| &ticks.dt)
+                .copied()
+                .collect(),
+            recording,
+            tick: 0,
+            next_input: 0,
+            next_checkpoint: 0,
+            players: HashMap::new(),
+            divergences: Vec::new(),
+        })
+    }
+
+    pub fn step(&mut self) -> ReplayStep {
+        self.verify();
+        let Some(&dt) = self.ticks.get(self.tick) else {
+            return ReplayStep::Finished {
+                ticks: self.ticks.len(),
+                divergences: std::mem::take(&mut self.divergences),
+            };
+        };
+        if !self.spawn_players() {
+            return ReplayStep::Waiting;
+        }
+
+        let tick = self.tick;
+        let mut inputs = Vec::new();
+        while let Some(input) = self.recording.inputs.get(self.next_input) {
+            if input.tick != tick {
+                break;
+            }
+            if let Some(&player_id) = self.players.get(&input.player) {
+                inputs.push((player_id, input.to_received()));
+            }
+            self.next_input += 1;
+        }
+        self.tick += 1;
+        ReplayStep::Tick { dt, inputs }
+    }
+
+    /// Spawns the stand-ins of the players joining and despawns the ones of the players leaving
+    /// at the current tick. Returns false while the stand-ins aren't set up yet.
+    fn spawn_players(&mut self) -> bool {
+        for departure in &self.recording.departures {
+            if departure.tick == self.tick {
+                if let Some(player_id) = self.players.remove(&departure.player) {
+                    entity::despawn(player_id);
+                }
+            }
+        }
+
+        let mut ready = true;
+        for (index, recorded) in self.recording.players.iter().enumerate() {
+            if recorded.joined != self.tick {
+                continue;
+            }
+
+            let player_id = *self
+                .players
+                .entry(index)
+                .or_insert_with(|| Entity::new().with_default(player()).spawn());
+            // the player only gets set up on the frame after spawning, it spawned wherever the
+            // spawn policy put it and gets moved to where it spawned in the recording
+            if !entity::has_component(player_id, player_mesh_ref()) {
+                ready = false;
+                continue;
+            }
+            let position = Vec3::from_array(recorded.translation);
+            physics::set_character_position(player_id, position);
+            entity::set_component(player_id, translation(), position);
+            entity::set_component(player_id, rotation(), Quat::from_array(recorded.rotation));
+        }
+        ready
+    }
+
+    /// Checks the stand-ins against the checkpoints before the current tick
+    fn verify(&mut self) {
+        while let Some(checkpoint) = self.recording.checkpoints.get(self.next_checkpoint) {
+            if checkpoint.tick > self.tick {
+                break;
+            }
+            self.next_checkpoint += 1;
+            let Some(&player_id) = self.players.get(&checkpoint.player) else { continue; };
+
+            let expected_translation = Vec3::from_array(checkpoint.translation);
+            let translation = entity::get_component(player_id, translation()).unwrap_or_default();
+            let states = if checkpoint.animation_states.is_empty() {
+                vec![]
+            } else {
+                animation_states(player_id)
+            };
+            if translation.distance(expected_translation) > REPLAY_TOLERANCE
+                || states != checkpoint.animation_states
+            {
+                self.divergences.push(ReplayDivergence {
+                    tick: checkpoint.tick,
+                    player: checkpoint.player,
+                    expected_translation,
+                    translation,
+                    expected_animation_states: checkpoint.animation_states.clone(),
+                    animation_states: states,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input_sequence::{received, Received},
+        movement::{MovementInput, MovementState, DEFAULT_JUMP_HEIGHT},
+    };
+
+    // two players walking, turning, dashing and jumping for a few seconds, with the ticks of a
+    // server running at an uneven frame rate
+    const FLAT_GROUND_RECORDING: &str = include_str!("../tests/recordings/flat_ground.toml");
+
+    struct FlatGroundPlayer {
+        position: Vec3,
+        /// height of the ground, the player spawned standing on it
+        ground: f32,
+        movement: MovementState,
+        input: MovementInput,
+        sequence: u32,
+    }
+
+    /// Replays a recording the way the server does, but without the engine: on flat ground at
+    /// the height every player spawned at and with nothing else to collide with, where the
+    /// character controller moves players exactly as far as they ask. The recordings it replays
+    /// stick to input the validator lets through unchanged. Returns where the players diverged
+    /// from the checkpoints.
+    fn replay_on_flat_ground(replay: &InputReplay) -> Vec<ReplayDivergence> {
+        let recording = &replay.recording;
+        let mut players: HashMap<usize, FlatGroundPlayer> = HashMap::new();
+        let mut divergences = vec![];
+        for tick in 0..=replay.ticks.len() {
+            for checkpoint in recording.checkpoints.iter().filter(|c| c.tick == tick) {
+                let expected_translation = Vec3::from_array(checkpoint.translation);
+                let translation = players[&checkpoint.player].position;
+                if translation.distance(expected_translation) > REPLAY_TOLERANCE {
+                    divergences.push(ReplayDivergence {
+                        tick,
+                        player: checkpoint.player,
+                        expected_translation,
+                        translation,
+                        expected_animation_states: vec![],
+                        animation_states: vec![],
+                    });
+                }
+            }
+            let Some(&dt) = replay.ticks.get(tick) else { break; };
+
+            for departure in recording.departures.iter().filter(|d| d.tick == tick) {
+                players.remove(&departure.player);
+            }
+            for (index, recorded) in recording.players.iter().enumerate() {
+                if recorded.joined != tick {
+                    continue;
+                }
+                let position = Vec3::from_array(recorded.translation);
+                let movement = MovementState {
+                    rotation: Quat::from_array(recorded.rotation),
+                    vertical_rotation_angle: 0.0,
+                    vertical_velocity: 0.0,
+                    is_grounded: true,
+                };
+                players.insert(
+                    index,
+                    FlatGroundPlayer {
+                        position,
+                        ground: position.z,
+                        movement,
+                        input: MovementInput::default(),
+                        sequence: 0,
+                    },
+                );
+            }
+
+            for input in recording.inputs.iter().filter(|i| i.tick == tick) {
+                let player = players.get_mut(&input.player).unwrap();
+                match &input.message {
+                    RecordedMessage::Input {
+                        directions,
+                        is_dashing,
+                        looks,
+                    } => {
+                        let Received::New { commands, .. } =
+                            received(player.sequence, input.sequence, looks.len() as u32)
+                        else {
+                            continue;
+                        };
+                        let new_commands = commands as usize;
+                        player.sequence = input.sequence;
+                        player.input.look += looks[looks.len() - new_commands..]
+                            .iter()
+                            .copied()
+                            .map(Vec2::from_array)
+                            .sum::<Vec2>();
+                        player.input.direction = Vec2::from_array(*directions.last().unwrap());
+                        player.input.is_dashing = *is_dashing.last().unwrap();
+                    }
+                    RecordedMessage::Action { is_jumping, .. } => {
+                        player.input.is_jumping |= is_jumping;
+                    }
+                }
+            }
+
+            for player in players.values_mut() {
+                player.movement.turn(player.input.look);
+                let velocity =
+                    player
+                        .movement
+                        .velocity(&player.input, true, DEFAULT_JUMP_HEIGHT, dt);
+                player.input.look = Vec2::ZERO;
+                player.input.is_jumping = false;
+                player.position += velocity * dt;
+                let grounded = player.position.z <= player.ground;
+                player.position.z = player.position.z.max(player.ground);
+                player.movement.collide(grounded, false);
+            }
+        }
+        divergences
+    }
+
+    #[test]
+    fn flat_ground_recording_replays_to_its_checkpoints() {
+        let replay = InputReplay::parse(FLAT_GROUND_RECORDING).unwrap();
+        assert!(replay.ticks.len() > 100);
+        assert_eq!(replay.recording.players.len(), 2);
+        assert!(replay.recording.checkpoints.len() >= 4);
+        let divergences: Vec<String> = replay_on_flat_ground(&replay)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert!(divergences.is_empty(), "{divergences:#?}");
+    }
+
+    #[test]
+    fn appended_entries_read_back_in_order() {
+        let mut source = String::new();
+        let mut append = |entry: RecordingEntry| {
+            source.push_str(&toml::to_string(&entry).unwrap());
+            source.push('\n');
+        };
+        append(RecordingEntry::Player([&RecordedPlayer {
+            joined: 0,
+            translation: [1.0, 2.0, 0.0],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+        }]));
+        append(RecordingEntry::Input([&RecordedInput {
+            tick: 0,
+            player: 0,
+            sequence: 1,
+            message: RecordedMessage::Input {
+                directions: vec![[1.0, 0.0]],
+                is_dashing: vec![false],
+                looks: vec![[0.1, 0.0]],
+            },
+        }]));
+        append(RecordingEntry::Ticks([&RecordedTicks {
+            dt: vec![1.0 / 60.0, 1.0 / 30.0],
+        }]));
+        append(RecordingEntry::Checkpoint([&RecordedCheckpoint {
+            tick: 2,
+            player: 0,
+            translation: [1.5, 2.0, 0.0],
+            animation_states: vec![],
+        }]));
+        append(RecordingEntry::Input([&RecordedInput {
+            tick: 2,
+            player: 0,
+            sequence: 2,
+            message: RecordedMessage::Action {
+                is_jumping: true,
+                is_punching: false,
+                view_time: 0.05,
+            },
+        }]));
+        append(RecordingEntry::Departure([&RecordedDeparture {
+            player: 0,
+            tick: 3,
+        }]));
+        append(RecordingEntry::Ticks([&RecordedTicks {
+            dt: vec![1.0 / 45.0],
+        }]));
+
+        let replay = InputReplay::parse(&source).unwrap();
+        assert_eq!(replay.ticks, [1.0 / 60.0, 1.0 / 30.0, 1.0 / 45.0]);
+        let recording = &replay.recording;
+        assert_eq!(recording.players[0].translation, [1.0, 2.0, 0.0]);
+        assert_eq!(
+            recording
+                .inputs
+                .iter()
+                .map(|input| (input.tick, input.sequence))
+                .collect::<Vec<_>>(),
+            [(0, 1), (2, 2)]
+        );
+        assert!(matches!(
+            recording.inputs[1].message,
+            RecordedMessage::Action {
+                is_jumping: true,
+                ..
+            }
+        ));
+        assert_eq!(recording.departures[0].tick, 3);
+        assert!(recording.checkpoints[0].animation_states.is_empty());
+    }
+
+    #[test]
+    fn every_tick_reaches_the_file_right_away() {
+        let path = std::env::temp_dir().join("every_tick_reaches_the_file_right_away.toml");
+        let path = path.to_str().unwrap().to_string();
+        let mut recorder = InputRecorder::new(path.clone()).unwrap();
+        recorder.record_tick(1.0 / 60.0, &[], &[]).unwrap();
+        recorder.record_tick(1.0 / 30.0, &[], &[]).unwrap();
+
+        // read back while the recording goes on, as if the server stopped right here
+        let replay = InputReplay::load(&path).unwrap();
+        assert_eq!(replay.ticks, [1.0 / 60.0, 1.0 / 30.0]);
+    }
+
+    #[test]
+    fn invalid_recording_is_reported() {
+        assert!(matches!(
+            InputReplay::parse("[[ticks]]\ndt = \"fast\""),
+            Err(RecordingError::Parse(_))
+        ));
+    }
+}
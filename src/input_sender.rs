@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+
+use crate::{
+    components::remote_view_time,
+    input_sequence::{next_sequence, FIRST_INPUT_SEQUENCE},
+    movement::MovementInput,
+    network_simulator::{simulate, NetworkConditions, SimulatedLink},
+};
+use ambient_api::prelude::*;
+
+// input messages per second, each one carries the input of the frames since the previous one
+const INPUT_SEND_RATE: f32 = 30.0;
+// input commands repeated in every message, a lost message is made up for by the next ones
+const INPUT_REDUNDANCY: usize = 4;
+
+/// The input of the frames between two input messages
+#[derive(Debug, Clone, Copy, Default)]
+struct InputCommand {
+    direction: Vec2,
+    is_dashing: bool,
+    look: Vec2,
+}
+
+impl InputCommand {
+    fn is_idle(&self) -> bool {
+        self.direction == Vec2::ZERO && !self.is_dashing && self.look == Vec2::ZERO
+    }
+}
+
+/// A message on its way to the server
+enum OutgoingInput {
+    Input(messages::Input),
+    Action(messages::InputAction),
+}
+
+impl OutgoingInput {
+    fn send(self) {
+        match self {
+            OutgoingInput::Input(msg) => msg.send_server_unreliable(),
+            OutgoingInput::Action(msg) => msg.send_server_reliable(),
+        }
+    }
+}
+
+/// Sends the movement input of the local player to the server, `INPUT_SEND_RATE` times a second
+/// over the unreliable channel. The frames between two messages get merged into one input
+/// command with the latest direction and dash state and the look summed up, numbered with the
+/// next input sequence. Every message carries the last `INPUT_REDUNDANCY` commands, so the
+/// server can make up for lost messages with the next one that arrives.
+///
+/// Once the input went idle the sender rests after `INPUT_REDUNDANCY` idle messages, enough for
+/// the server to see the player stopped even if some of them got lost. Jumps and punches don't
+/// wait for the next message and go out right away as a reliable `InputAction`.
+///
+/// In debug builds the messages can go through a simulated network first, see `simulate`.
+pub struct InputSender {
+    /// sequence of the command the current frames are gathered into
+    sequence: u32,
+    command: InputCommand,
+    since_send: f32,
+    /// the commands sent last, oldest first
+    recent: VecDeque<InputCommand>,
+    /// idle messages sent since the input last changed
+    idle_messages: usize,
+    simulated_link: Option<SimulatedLink<OutgoingInput>>,
+}
+
+impl Default for InputSender {
+    fn default() -> Self {
+        Self {
+            sequence: FIRST_INPUT_SEQUENCE,
+            command: InputCommand::default(),
+            since_send: 0.0,
+            recent: VecDeque::new(),
+            idle_messages: 0,
+            simulated_link: None,
+        }
+    }
+}
+
+impl InputSender {
+    /// Adds the input of a frame to the next input message. Returns the sequence of the input
+    /// command it becomes part of, or `None` while the sender rests.
+    pub fn sample(&mut self, input: &MovementInput, is_punching: bool) -> Option<u32> {
+        if input.is_jumping || is_punching {
+            let view_time =
+                entity::get_component(entity::resources(), remote_view_time()).unwrap_or_default();
+            self.transmit(OutgoingInput::Action(messages::InputAction::new(
+                self.sequence,
+                input.is_jumping,
+                is_punching,
+                view_time,
+            )));
+        }
+
+        let is_idle = input.direction == Vec2::ZERO
+            && !input.is_dashing
+            && !input.is_jumping
+            && input.look == Vec2::ZERO;
+        if is_idle && self.is_resting() {
+            return None;
+        }
+        if !is_idle {
+            self.idle_messages = 0;
+        }
+
+        self.command.direction = input.direction;
+        self.command.is_dashing = input.is_dashing;
+        self.command.look += input.look;
+        Some(self.sequence)
+    }
+
+    /// Sends the messages from now on through a network with `conditions`, in debug builds
+    pub fn simulate(&mut self, conditions: NetworkConditions) {
+        simulate(&mut self.simulated_link, conditions);
+    }
+
+    /// Sends the gathered input once the send interval passed. Frames without a sample count as
+    /// idle input.
+    pub fn update(&mut self, dt: f32) {
+        if let Some(link) = self.simulated_link.as_mut() {
+            for message in link.receive(dt) {
+                message.send();
+            }
+        }
+
+        if self.is_resting() {
+            self.since_send = 0.0;
+            return;
+        }
+
+        let interval = 1.0 / INPUT_SEND_RATE;
+        self.since_send += dt;
+        if self.since_send < interval {
+            return;
+        }
+        // a long frame sends once instead of catching up with a burst of messages
+        self.since_send = (self.since_send - interval).min(interval);
+        self.send();
+    }
+
+    /// Sends a message to the server, through the simulated network if there is one
+    fn transmit(&mut self, message: OutgoingInput) {
+        let Some(link) = self.simulated_link.as_mut() else {
+            message.send();
+            return;
+        };
+        match message {
+            OutgoingInput::Input(_) => link.send_unreliable(message),
+            OutgoingInput::Action(_) => link.send_reliable(message),
+        }
+    }
+
+    fn is_resting(&self) -> bool {
+        self.idle_messages >= INPUT_REDUNDANCY
+    }
+
+    fn send(&mut self) {
+        let command = std::mem::take(&mut self.command);
+        if command.is_idle() {
+            self.idle_messages += 1;
+        }
+        self.recent.push_back(command);
+        if self.recent.len() > INPUT_REDUNDANCY {
+            self.recent.pop_front();
+        }
+
+        let message = messages::Input::new(
+            self.sequence,
+            self.recent
+                .iter()
+                .map(|command| command.direction)
+                .collect(),
+            self.recent
+                .iter()
+                .map(|command| command.is_dashing)
+                .collect(),
+            self.recent.iter().map(|command| command.look).collect(),
+        );
+        self.transmit(OutgoingInput::Input(message));
+        self.sequence = next_sequence(self.sequence);
+    }
+}
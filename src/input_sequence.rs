@@ -0,0 +1,188 @@
+use crate::components::{player_input_sequence, player_inputs_dropped, player_inputs_stale};
+use ambient_api::prelude::*;
+
+// every input message carries a sequence number, counting up from 1 with every message the
+// client sends. 0 stands for a player the server didn't get any input from yet.
+
+/// Sequence number of the first input message a client sends
+pub const FIRST_INPUT_SEQUENCE: u32 = 1;
+
+/// The sequence number of the input message after `sequence`, skipping 0 when wrapping around
+pub fn next_sequence(sequence: u32) -> u32 {
+    sequence.wrapping_add(1).max(FIRST_INPUT_SEQUENCE)
+}
+
+/// Whether `sequence` was sent after `than`. Sequence numbers wrap around, so this holds as long
+/// as the two are less than half the range apart.
+pub fn is_newer(sequence: u32, than: u32) -> bool {
+    (sequence.wrapping_sub(than) as i32) > 0
+}
+
+/// Server side bookkeeping of the input messages a player sent. Messages can get lost or arrive
+/// out of order, so only commands newer than `player_input_sequence` are applied. Commands that
+/// never arrived, not even repeated in a later message, are counted in `player_inputs_dropped`
+/// and messages arriving after a newer one in `player_inputs_stale`.
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+pub struct InputSequence(pub EntityId);
+impl InputSequence {
+    /// Records a received input message whose newest command is `sequence`, repeating `count`
+    /// commands up to it. Returns how many of those commands are newer than the last one applied,
+    /// 0 if the whole message is stale and must be ignored.
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn receive(&self, sequence: u32, count: u32) -> u32 {
+        let player_id = self.0;
+        match received(self.last(), sequence, count) {
+            Received::Stale => {
+                entity::mutate_component(player_id, player_inputs_stale(), |stale| *stale += 1);
+                0
+            }
+            Received::New { commands, dropped } => {
+                if dropped > 0 {
+                    entity::mutate_component(player_id, player_inputs_dropped(), |count| {
+                        *count += dropped;
+                    });
+                }
+                entity::set_component(player_id, player_input_sequence(), sequence);
+                commands
+            }
+        }
+    }
+
+    /// Sequence number of the newest input message applied
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn last(&self) -> u32 {
+        entity::get_component(self.0, player_input_sequence()).unwrap_or_default()
+    }
+}
+
+/// What an input message brings, given the newest command applied before it
+#[derive(Debug, PartialEq, Eq)]
+pub enum Received {
+    /// nothing newer than what was applied already
+    Stale,
+    /// `commands` commands newer than the last one applied, after `dropped` ones that never
+    /// arrived
+    New { commands: u32, dropped: u32 },
+}
+
+/// Sorts out an input message whose newest command is `sequence`, repeating `count` commands up
+/// to it, with `last` the newest command applied so far
+pub fn received(last: u32, sequence: u32, count: u32) -> Received {
+    if !is_newer(sequence, last) {
+        return Received::Stale;
+    }
+    let mut advanced = sequence.wrapping_sub(last);
+    if sequence < last {
+        // wrapped around, past the 0 never sent
+        advanced -= 1;
+    }
+    let commands = advanced.min(count);
+    Received::New {
+        commands,
+        dropped: advanced - commands,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences_count_up_and_skip_0_when_wrapping() {
+        assert_eq!(next_sequence(0), FIRST_INPUT_SEQUENCE);
+        assert_eq!(next_sequence(1), 2);
+        assert_eq!(next_sequence(u32::MAX - 1), u32::MAX);
+        assert_eq!(next_sequence(u32::MAX), FIRST_INPUT_SEQUENCE);
+    }
+
+    #[test]
+    fn newer_sequences_survive_wrapping() {
+        assert!(is_newer(2, 1));
+        assert!(!is_newer(1, 2));
+        assert!(!is_newer(5, 5));
+        assert!(is_newer(FIRST_INPUT_SEQUENCE, 0));
+
+        // across the wrap, 0 never being sent doesn't matter
+        assert!(is_newer(FIRST_INPUT_SEQUENCE, u32::MAX));
+        assert!(is_newer(next_sequence(u32::MAX), u32::MAX));
+        assert!(!is_newer(u32::MAX, FIRST_INPUT_SEQUENCE));
+        assert!(is_newer(10, u32::MAX - 10));
+
+        // up to half the range apart
+        assert!(is_newer(i32::MAX as u32, 0));
+        assert!(!is_newer(i32::MAX as u32 + 1, 0));
+    }
+
+    #[test]
+    fn messages_in_order_apply_their_newest_command() {
+        assert_eq!(
+            received(0, FIRST_INPUT_SEQUENCE, 1),
+            Received::New {
+                commands: 1,
+                dropped: 0
+            }
+        );
+        assert_eq!(
+            received(7, 8, 4),
+            Received::New {
+                commands: 1,
+                dropped: 0
+            }
+        );
+    }
+
+    #[test]
+    fn duplicate_and_stale_messages_are_ignored() {
+        assert_eq!(received(8, 8, 4), Received::Stale);
+        assert_eq!(received(8, 5, 4), Received::Stale);
+        assert_eq!(received(FIRST_INPUT_SEQUENCE, u32::MAX, 4), Received::Stale);
+        assert_eq!(received(8, 0, 1), Received::Stale);
+    }
+
+    #[test]
+    fn redundant_commands_fill_in_lost_messages() {
+        // 8 and 9 got lost, 10 repeats them
+        assert_eq!(
+            received(7, 10, 4),
+            Received::New {
+                commands: 3,
+                dropped: 0
+            }
+        );
+        // the repeats reach back exactly to the last command applied
+        assert_eq!(
+            received(6, 10, 4),
+            Received::New {
+                commands: 4,
+                dropped: 0
+            }
+        );
+        // more got lost than 10 repeats
+        assert_eq!(
+            received(3, 10, 4),
+            Received::New {
+                commands: 4,
+                dropped: 3
+            }
+        );
+    }
+
+    #[test]
+    fn redundant_commands_overlap_across_the_wrap() {
+        // u32::MAX, 1 and 2 are new, u32::MAX - 1 was applied already
+        assert_eq!(
+            received(u32::MAX - 1, 2, 4),
+            Received::New {
+                commands: 3,
+                dropped: 0
+            }
+        );
+        assert_eq!(
+            received(u32::MAX - 5, 2, 4),
+            Received::New {
+                commands: 4,
+                dropped: 3
+            }
+        );
+    }
+}
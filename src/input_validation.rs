@@ -0,0 +1,417 @@
+use std::fmt;
+
+use crate::{
+    components::{
+        dash_cooldown, is_dashing, player_dash_requested, player_input_budget,
+        player_input_flagged, player_input_last_violation, player_input_suspicion,
+        player_input_violations, player_look,
+    },
+    movement::{DASH_COOLDOWN, LOOK_SENSITIVITY},
+};
+use ambient_api::prelude::*;
+
+// input messages a client may send per second on average. The input sender sends 30, jumps and
+// punches add a few more.
+const INPUT_MESSAGE_RATE: f32 = 45.0;
+// input messages a client may send at once, e.g. when messages held up by the network arrive
+// together
+pub const INPUT_MESSAGE_BURST: f32 = 30.0;
+
+// input commands an input message may repeat, far more than any client sends
+pub const MAX_INPUT_COMMANDS: usize = 16;
+
+// fastest a player can turn, in radians per second. Flicking the mouse doesn't come close.
+const MAX_TURN_SPEED: f32 = 6.0 * std::f32::consts::PI;
+// turning held back when a player turns faster than that, in seconds at `MAX_TURN_SPEED`
+const MAX_TURN_BACKLOG: f32 = 0.25;
+
+// the dash cooldown runs on both ends, the server allows for the input messages of the dash
+// arriving closer together than they were sent
+const DASH_COOLDOWN_TOLERANCE: f32 = 0.1; // seconds
+
+// every impossible input adds 1 to the suspicion of a player, which wears off over time. Only a
+// client sending impossible input over and over again gets flagged.
+const SUSPICION_DECAY: f32 = 0.5; // per second
+const SUSPICION_THRESHOLD: f32 = 10.0;
+
+/// Input no unmodified client sends
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InputViolation {
+    /// The commands of an input message don't line up
+    Malformed,
+    /// More input messages than the rate limit allows
+    RateLimited,
+    /// A direction not made of movement keys
+    Direction,
+    /// A look that isn't a number
+    Look,
+    /// A dash started before the dash cooldown ran out
+    DashCooldown,
+    /// A dash started moving backwards, while the dash is a double tap of forward
+    DashBackwards,
+}
+
+impl fmt::Display for InputViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputViolation::Malformed => write!(f, "malformed input message"),
+            InputViolation::RateLimited => {
+                write!(
+                    f,
+                    "more than {INPUT_MESSAGE_RATE} input messages per second"
+                )
+            }
+            InputViolation::Direction => write!(f, "impossible movement direction"),
+            InputViolation::Look => write!(f, "look is not a number"),
+            InputViolation::DashCooldown => write!(f, "dash during the dash cooldown"),
+            InputViolation::DashBackwards => write!(f, "dash moving backwards"),
+        }
+    }
+}
+
+/// Server side checks of the input a player's client sends. Nothing coming from a client is
+/// trusted: impossible input is clamped to what the client could have sent and counted in
+/// `player_input_violations`. A player whose `player_input_suspicion` builds up past
+/// `SUSPICION_THRESHOLD` gets `player_input_flagged`. Like the other input diagnostics of the
+/// player, e.g. `player_inputs_dropped`, these show up in the debugger rather than the log.
+pub struct InputValidator(pub EntityId);
+impl InputValidator {
+    /// Advances the rate limit, the dash cooldown and the suspicion of the player by `dt`
+    pub fn update(&self, dt: f32) {
+        self.with_state(|state| state.update(dt));
+    }
+
+    /// Takes a received input message out of the rate limit. Returns false if the client sends
+    /// too many and the message must be dropped.
+    pub fn allow_message(&self) -> bool {
+        self.with_state(ValidationState::allow_message)
+    }
+
+    /// Clamps a movement direction to what the movement keys produce, -1, 0 or 1 on each axis
+    pub fn direction(&self, direction: Vec2) -> Vec2 {
+        self.with_state(|state| state.direction(direction))
+    }
+
+    /// Drops a look that isn't a number. How far it turns is limited by `turn`.
+    pub fn look(&self, look: Vec2) -> Vec2 {
+        self.with_state(|state| state.look(look))
+    }
+
+    /// Whether the player dashes, given whether the newest command `requested` a dash and the
+    /// `direction` it moves in. A dash only starts when the request does and lasts until the
+    /// requests stop, so a refused dash stays refused until the client lets go of it.
+    pub fn dash(&self, requested: bool, direction: Vec2) -> bool {
+        self.with_state(|state| state.dash(requested, direction))
+    }
+
+    /// Takes the part of the received `look` the player can turn by in a frame lasting `dt`
+    /// seconds. The rest stays in `player_look` for the next frames, up to `MAX_TURN_BACKLOG`.
+    pub fn turn(&self, look: Vec2, dt: f32) -> Vec2 {
+        let (turn, backlog) = split_turn(look, dt);
+        entity::set_component(self.0, player_look(), backlog);
+        turn
+    }
+
+    /// Counts an impossible input of the player, flagging them once it happens too often
+    pub fn report(&self, violation: InputViolation) {
+        self.with_state(|state| state.report(violation));
+    }
+
+    fn with_state<R>(&self, check: impl FnOnce(&mut ValidationState) -> R) -> R {
+        let mut state = ValidationState::load(self.0);
+        let result = check(&mut state);
+        state.store(self.0);
+        result
+    }
+}
+
+/// What the validator keeps track of for a player between messages, stored in the player's
+/// components. The checks only work on this, so they don't need the ECS.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct ValidationState {
+    /// input messages the player may still send right now
+    budget: f32,
+    dash_cooldown: f32,
+    /// whether the newest command received requested a dash
+    dash_requested: bool,
+    is_dashing: bool,
+    suspicion: f32,
+    violations: u32,
+    /// reported since the state got loaded
+    last_violation: Option<InputViolation>,
+    flagged: bool,
+}
+
+impl ValidationState {
+    fn load(player_id: EntityId) -> Self {
+        Self {
+            budget: entity::get_component(player_id, player_input_budget()).unwrap_or_default(),
+            dash_cooldown: entity::get_component(player_id, dash_cooldown()).unwrap_or_default(),
+            dash_requested: entity::get_component(player_id, player_dash_requested())
+                .unwrap_or_default(),
+            is_dashing: entity::get_component(player_id, is_dashing()).unwrap_or_default(),
+            suspicion: entity::get_component(player_id, player_input_suspicion())
+                .unwrap_or_default(),
+            violations: entity::get_component(player_id, player_input_violations())
+                .unwrap_or_default(),
+            last_violation: None,
+            flagged: entity::get_component(player_id, player_input_flagged()).unwrap_or_default(),
+        }
+    }
+
+    fn store(&self, player_id: EntityId) {
+        entity::set_component(player_id, player_input_budget(), self.budget);
+        entity::set_component(player_id, dash_cooldown(), self.dash_cooldown);
+        entity::set_component(player_id, player_dash_requested(), self.dash_requested);
+        entity::set_component(player_id, is_dashing(), self.is_dashing);
+        entity::set_component(player_id, player_input_suspicion(), self.suspicion);
+        entity::set_component(player_id, player_input_violations(), self.violations);
+        if let Some(violation) = self.last_violation {
+            entity::set_component(
+                player_id,
+                player_input_last_violation(),
+                violation.to_string(),
+            );
+        }
+        entity::set_component(player_id, player_input_flagged(), self.flagged);
+    }
+
+    fn update(&mut self, dt: f32) {
+        self.budget = (self.budget + INPUT_MESSAGE_RATE * dt).min(INPUT_MESSAGE_BURST);
+        self.dash_cooldown = (self.dash_cooldown - dt).max(0.0);
+        self.suspicion = (self.suspicion - SUSPICION_DECAY * dt).max(0.0);
+    }
+
+    fn allow_message(&mut self) -> bool {
+        if self.budget < 1.0 {
+            self.report(InputViolation::RateLimited);
+            return false;
+        }
+        self.budget -= 1.0;
+        true
+    }
+
+    fn direction(&mut self, direction: Vec2) -> Vec2 {
+        let clamp = |axis: f32| {
+            if axis.is_finite() {
+                axis.clamp(-1.0, 1.0).round()
+            } else {
+                0.0
+            }
+        };
+        let clamped = vec2(clamp(direction.x), clamp(direction.y));
+        if clamped != direction {
+            self.report(InputViolation::Direction);
+        }
+        clamped
+    }
+
+    fn look(&mut self, look: Vec2) -> Vec2 {
+        if look.is_finite() {
+            look
+        } else {
+            self.report(InputViolation::Look);
+            Vec2::ZERO
+        }
+    }
+
+    fn dash(&mut self, requested: bool, direction: Vec2) -> bool {
+        let was_requested = std::mem::replace(&mut self.dash_requested, requested);
+        self.is_dashing = if !requested {
+            if self.is_dashing {
+                self.dash_cooldown = DASH_COOLDOWN - DASH_COOLDOWN_TOLERANCE;
+            }
+            false
+        } else if was_requested {
+            self.is_dashing
+        } else if self.dash_cooldown > 0.0 {
+            self.report(InputViolation::DashCooldown);
+            false
+        } else if direction.x < 0.0 {
+            self.report(InputViolation::DashBackwards);
+            false
+        } else {
+            true
+        };
+        self.is_dashing
+    }
+
+    fn report(&mut self, violation: InputViolation) {
+        self.last_violation = Some(violation);
+        self.violations += 1;
+        self.suspicion += 1.0;
+        if self.suspicion >= SUSPICION_THRESHOLD {
+            self.flagged = true;
+        }
+    }
+}
+
+/// Splits a received `look` into the part the player can turn by in a frame lasting `dt` seconds
+/// and the part held back for the next frames
+fn split_turn(look: Vec2, dt: f32) -> (Vec2, Vec2) {
+    // look is mouse movement in pixels, `LOOK_SENSITIVITY` turns it into radians
+    let max_look = MAX_TURN_SPEED * dt / LOOK_SENSITIVITY;
+    let max_backlog = MAX_TURN_SPEED * MAX_TURN_BACKLOG / LOOK_SENSITIVITY;
+    let turn = look.clamp(Vec2::splat(-max_look), Vec2::splat(max_look));
+    let backlog = (look - turn).clamp(Vec2::splat(-max_backlog), Vec2::splat(max_backlog));
+    (turn, backlog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fresh() -> ValidationState {
+        ValidationState {
+            budget: INPUT_MESSAGE_BURST,
+            ..ValidationState::default()
+        }
+    }
+
+    #[test]
+    fn directions_are_clamped_to_the_movement_keys() {
+        let cases = [
+            (vec2(1.0, -1.0), vec2(1.0, -1.0), false),
+            (vec2(0.0, 0.0), vec2(0.0, 0.0), false),
+            (vec2(2.0, 0.0), vec2(1.0, 0.0), true),
+            (vec2(0.4, -0.6), vec2(0.0, -1.0), true),
+            (vec2(-100.0, 3.0), vec2(-1.0, 1.0), true),
+            (vec2(f32::NAN, 1.0), vec2(0.0, 1.0), true),
+            (vec2(1.0, f32::INFINITY), vec2(1.0, 0.0), true),
+        ];
+        for (direction, expected, violation) in cases {
+            let mut state = fresh();
+            assert_eq!(state.direction(direction), expected, "{direction}");
+            assert_eq!(state.violations, violation as u32, "{direction}");
+        }
+    }
+
+    #[test]
+    fn looks_that_are_not_numbers_are_dropped() {
+        let mut state = fresh();
+        assert_eq!(state.look(vec2(-250.0, 12.5)), vec2(-250.0, 12.5));
+        assert_eq!(state.violations, 0);
+        assert_eq!(state.look(vec2(f32::NAN, 1.0)), Vec2::ZERO);
+        assert_eq!(state.look(vec2(0.0, f32::NEG_INFINITY)), Vec2::ZERO);
+        assert_eq!(state.violations, 2);
+        assert_eq!(state.last_violation, Some(InputViolation::Look));
+    }
+
+    #[test]
+    fn turning_is_limited_to_the_max_turn_speed() {
+        let dt = 1.0 / 60.0;
+        let max_look = MAX_TURN_SPEED * dt / LOOK_SENSITIVITY;
+        let max_backlog = MAX_TURN_SPEED * MAX_TURN_BACKLOG / LOOK_SENSITIVITY;
+
+        let (turn, backlog) = split_turn(vec2(10.0, -10.0), dt);
+        assert_eq!((turn, backlog), (vec2(10.0, -10.0), Vec2::ZERO));
+
+        // a flick too fast for a frame finishes over the next ones
+        let (turn, backlog) = split_turn(vec2(max_look * 3.0, 0.0), dt);
+        assert_eq!(turn, vec2(max_look, 0.0));
+        assert!((backlog.x - max_look * 2.0).abs() < 1e-3);
+        let (turn, backlog) = split_turn(backlog, dt);
+        assert!((turn.x - max_look).abs() < 1e-3 && (backlog.x - max_look).abs() < 1e-3);
+
+        // anything beyond the backlog is dropped
+        let (turn, backlog) = split_turn(vec2(-1e9, 1e9), dt);
+        assert_eq!(turn, vec2(-max_look, max_look));
+        assert_eq!(backlog, vec2(-max_backlog, max_backlog));
+    }
+
+    #[test]
+    fn dash_spam_inside_the_cooldown_is_refused() {
+        let mut state = fresh();
+        assert!(state.dash(true, Vec2::X));
+        // held, the dash goes on
+        assert!(state.dash(true, Vec2::X));
+        assert!(!state.dash(false, Vec2::X));
+        assert_eq!(state.dash_cooldown, DASH_COOLDOWN - DASH_COOLDOWN_TOLERANCE);
+
+        // tapping again during the cooldown is refused, and stays refused while held
+        state.update(0.1);
+        assert!(!state.dash(true, Vec2::X));
+        assert!(!state.dash(true, Vec2::X));
+        assert!(!state.dash(false, Vec2::X));
+        assert!(!state.dash(true, Vec2::X));
+        assert_eq!(state.violations, 2);
+        assert_eq!(state.last_violation, Some(InputViolation::DashCooldown));
+
+        // a refused dash doesn't start the cooldown over
+        state.update(DASH_COOLDOWN);
+        assert!(!state.dash(false, Vec2::X));
+        assert!(state.dash(true, Vec2::X));
+        assert_eq!(state.violations, 2);
+    }
+
+    #[test]
+    fn dashes_do_not_start_backwards() {
+        let mut state = fresh();
+        assert!(!state.dash(true, vec2(-1.0, 0.0)));
+        assert_eq!(state.last_violation, Some(InputViolation::DashBackwards));
+        assert!(!state.dash(false, Vec2::ZERO));
+        assert!(state.dash(true, vec2(0.0, 1.0)));
+        // turning around mid-dash is fine
+        assert!(state.dash(true, vec2(-1.0, 0.0)));
+        assert_eq!(state.violations, 1);
+    }
+
+    #[test]
+    fn bursts_over_the_rate_limit_are_dropped() {
+        let mut state = fresh();
+        let burst = INPUT_MESSAGE_BURST as usize;
+        assert!((0..burst).all(|_| state.allow_message()));
+        assert!(!state.allow_message());
+        assert!(!state.allow_message());
+        assert_eq!(state.violations, 2);
+        assert_eq!(state.last_violation, Some(InputViolation::RateLimited));
+
+        // the budget refills at the rate limit, up to a burst
+        state.update(0.5);
+        let allowed = (0..burst).take_while(|_| state.allow_message()).count();
+        assert_eq!(allowed, (INPUT_MESSAGE_RATE * 0.5) as usize);
+        state.update(60.0);
+        assert_eq!(state.budget, INPUT_MESSAGE_BURST);
+
+        // sending at the rate limit goes on forever
+        let mut state = fresh();
+        for _ in 0..1000 {
+            state.update(1.0 / INPUT_MESSAGE_RATE);
+            assert!(state.allow_message());
+        }
+        assert_eq!(state.violations, 0);
+    }
+
+    #[test]
+    fn repeat_offenders_get_flagged() {
+        let mut state = fresh();
+        let threshold = SUSPICION_THRESHOLD as u32;
+        for _ in 1..threshold {
+            state.report(InputViolation::Malformed);
+        }
+        assert!(!state.flagged);
+
+        // suspicion wears off, a violation now and then never adds up
+        state.update(2.0 / SUSPICION_DECAY);
+        state.report(InputViolation::Malformed);
+        assert!(!state.flagged);
+        for _ in 0..100 {
+            state.update(1.0 / SUSPICION_DECAY);
+            state.report(InputViolation::Direction);
+        }
+        assert!(!state.flagged);
+        assert!(state.suspicion < SUSPICION_THRESHOLD);
+
+        // while a burst of them does
+        for _ in 0..threshold {
+            state.report(InputViolation::Direction);
+        }
+        assert!(state.flagged);
+        assert_eq!(state.violations, 2 * threshold + 100);
+
+        // and flagged stays flagged
+        state.update(60.0);
+        assert_eq!(state.suspicion, 0.0);
+        assert!(state.flagged);
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::components::{
+    interpolated_translation, player_mesh_ref, player_snapshot_time, remote_view_time,
+};
+use ambient_api::{
+    components::core::{
+        player::{local_user_id, player, user_id},
+        transform::{rotation, translation},
+    },
+    prelude::*,
+};
+
+// remote players are shown this far in the past, so there usually is a newer snapshot to
+// interpolate towards even if an update or two got lost or arrived late
+const INTERPOLATION_DELAY: f32 = 0.1; // seconds
+
+// how long a remote player keeps moving past its newest snapshot when updates are late
+const MAX_EXTRAPOLATION: f32 = 0.1; // seconds
+
+const MAX_SNAPSHOTS: usize = 32; // per remote player
+
+// how much every new snapshot moves the estimate of the server clock, smooths out the jitter of
+// their arrival
+const CLOCK_SMOOTHING: f32 = 0.1;
+
+/// Replicated transform of a remote player at `time` on the server clock
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    time: f32,
+    translation: Vec3,
+    rotation: Quat,
+}
+
+impl Snapshot {
+    fn lerp(&self, to: &Snapshot, t: f32) -> Snapshot {
+        Snapshot {
+            time: self.time + (to.time - self.time) * t,
+            translation: self.translation.lerp(to.translation, t),
+            rotation: self.rotation.slerp(to.rotation, t),
+        }
+    }
+}
+
+#[derive(Default)]
+struct RemotePlayer {
+    /// oldest first
+    snapshots: VecDeque<Snapshot>,
+    /// rotation the server spawned the mech mesh with, relative to the player
+    mesh_rotation: Option<Quat>,
+}
+
+impl RemotePlayer {
+    /// Adds a snapshot that just arrived. Returns false for one that isn't newer than the newest
+    /// snapshot, which got replicated again or arrived late and is dropped.
+    fn push(&mut self, snapshot: Snapshot) -> bool {
+        if self
+            .snapshots
+            .back()
+            .is_some_and(|newest| newest.time >= snapshot.time)
+        {
+            return false;
+        }
+        self.snapshots.push_back(snapshot);
+        true
+    }
+
+    /// The pose at `time`, interpolated between the snapshots around it. Before the oldest
+    /// snapshot it is the oldest one, after the newest the position keeps moving for up to
+    /// `MAX_EXTRAPOLATION` seconds.
+    fn sample(&self, time: f32) -> Option<Snapshot> {
+        let newest = *self.snapshots.back()?;
+        let Some(next) = self
+            .snapshots
+            .iter()
+            .position(|snapshot| snapshot.time > time)
+        else {
+            // updates are late, keep moving the way the last two snapshots did for a while.
+            // Only the position is extrapolated, the rotations stay at the newest snapshot.
+            let Some(previous) = self.snapshots.iter().rev().nth(1) else {
+                return Some(newest);
+            };
+            let extrapolation = (time - newest.time).min(MAX_EXTRAPOLATION);
+            let velocity =
+                (newest.translation - previous.translation) / (newest.time - previous.time);
+            return Some(Snapshot {
+                translation: newest.translation + velocity * extrapolation,
+                ..newest
+            });
+        };
+        if next == 0 {
+            return Some(self.snapshots[0]);
+        }
+
+        let (from, to) = (&self.snapshots[next - 1], &self.snapshots[next]);
+        Some(from.lerp(to, (time - from.time) / (to.time - from.time)))
+    }
+
+    /// Drops the snapshots that are too old to be interpolated from at `time` anymore
+    fn trim(&mut self, time: f32) {
+        while self.snapshots.len() > MAX_SNAPSHOTS
+            || (self.snapshots.len() > 2 && self.snapshots[1].time <= time)
+        {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+/// Smooths out the movement of remote players. Their transforms only change when an update
+/// from the server arrives, so at a low update rate they jitter. Instead every update is kept
+/// as a snapshot, stamped with the server time in `player_snapshot_time`, and the players are
+/// shown `INTERPOLATION_DELAY` seconds in the past, interpolated between the snapshots around
+/// that time.
+///
+/// The replicated `translation` and `rotation` of the players stay untouched, the mech mesh is
+/// offset from the player to show the interpolated pose instead. The interpolated position is
+/// also stored in `interpolated_translation` for whatever else floats above the mech. The view
+/// rotation isn't interpolated, a remote mech has nothing to show it with.
+#[derive(Default)]
+pub struct RemoteInterpolation {
+    clock: f32,
+    /// estimated server time minus `clock`
+    server_offset: Option<f32>,
+    /// server time of the newest snapshot of any player, the local one included
+    newest_snapshot_time: Option<f32>,
+    players: HashMap<EntityId, RemotePlayer>,
+}
+
+impl RemoteInterpolation {
+    pub fn update(&mut self, dt: f32) {
+        self.clock += dt;
+
+        // the local player is predicted instead, but its updates keep the estimate of the server
+        // clock going when there are no remote players, actions need a view time all the same
+        let local_user = entity::get_component(entity::resources(), local_user_id());
+        let (local_players, remote_players): (Vec<EntityId>, Vec<EntityId>) =
+            entity::get_all(player())
+                .into_iter()
+                .partition(|&id| entity::get_component(id, user_id()) == local_user);
+        for player_id in local_players {
+            if let Some(time) = entity::get_component(player_id, player_snapshot_time()) {
+                self.sync_clock(time);
+            }
+        }
+        self.players.retain(|id, _| remote_players.contains(id));
+        for &player_id in &remote_players {
+            self.record(player_id);
+        }
+
+        let Some(server_offset) = self.server_offset else { return; };
+        let render_time = self.clock + server_offset - INTERPOLATION_DELAY;
+        // actions are stamped with it, so the server resolves them against what the player saw
+        entity::add_component(entity::resources(), remote_view_time(), render_time);
+        for (&player_id, remote) in &mut self.players {
+            if let Some(pose) = remote.sample(render_time) {
+                show(player_id, remote, &pose);
+            }
+            remote.trim(render_time);
+        }
+    }
+
+    /// Keeps the replicated transform of a player if the server updated it since the last frame
+    fn record(&mut self, player_id: EntityId) {
+        let (Some(time), Some(player_translation), Some(player_rotation)) = (
+            entity::get_component(player_id, player_snapshot_time()),
+            entity::get_component(player_id, translation()),
+            entity::get_component(player_id, rotation()),
+        ) else {
+            return;
+        };
+
+        let remote = self.players.entry(player_id).or_default();
+        let snapshot = Snapshot {
+            time,
+            translation: player_translation,
+            rotation: player_rotation,
+        };
+        if remote.push(snapshot) {
+            self.sync_clock(time);
+        }
+    }
+
+    /// Moves the estimate of the server clock towards the time of a snapshot that just arrived.
+    /// A tick stamps every player with the same time, only the first of them counts.
+    fn sync_clock(&mut self, time: f32) {
+        if self
+            .newest_snapshot_time
+            .is_some_and(|newest| newest >= time)
+        {
+            return;
+        }
+        self.newest_snapshot_time = Some(time);
+
+        let offset = time - self.clock;
+        self.server_offset = Some(match self.server_offset {
+            Some(current) => current + (offset - current) * CLOCK_SMOOTHING,
+            None => offset,
+        });
+    }
+}
+
+fn show(player_id: EntityId, remote: &mut RemotePlayer, pose: &Snapshot) {
+    entity::add_component(player_id, interpolated_translation(), pose.translation);
+
+    // the mesh is a child of the player, offset it from the replicated transform to the pose
+    let Some(mesh_id) = entity::get_component(player_id, player_mesh_ref()) else { return; };
+    let (Some(player_translation), Some(player_rotation)) = (
+        entity::get_component(player_id, translation()),
+        entity::get_component(player_id, rotation()),
+    ) else {
+        return;
+    };
+    let mesh_rotation = *remote.mesh_rotation.get_or_insert_with(|| {
+        entity::get_component(mesh_id, rotation()).unwrap_or(Quat::IDENTITY)
+    });
+    let to_player = player_rotation.inverse();
+    entity::set_component(
+        mesh_id,
+        translation(),
+        to_player * (pose.translation - player_translation),
+    );
+    entity::set_component(
+        mesh_id,
+        rotation(),
+        to_player * pose.rotation * mesh_rotation,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A snapshot of a player moving along x at 10 units per second and turning at 1 radian per
+    /// second
+    fn snapshot(time: f32) -> Snapshot {
+        Snapshot {
+            time,
+            translation: Vec3::X * time * 10.0,
+            rotation: Quat::from_rotation_z(time),
+        }
+    }
+
+    fn remote_player(times: &[f32]) -> RemotePlayer {
+        let mut remote = RemotePlayer::default();
+        for &time in times {
+            assert!(remote.push(snapshot(time)));
+        }
+        remote
+    }
+
+    fn assert_pose(pose: Option<Snapshot>, x: f32, angle: f32) {
+        let pose = pose.unwrap();
+        assert!(
+            (pose.translation.x - x).abs() < 1e-3,
+            "{}",
+            pose.translation
+        );
+        assert!(pose.rotation.angle_between(Quat::from_rotation_z(angle)) < 1e-3);
+    }
+
+    #[test]
+    fn interpolates_between_the_snapshots_around() {
+        // updates at an uneven rate
+        let remote = remote_player(&[1.0, 1.05, 1.2, 1.25]);
+        for time in [1.0, 1.02, 1.05, 1.1, 1.19, 1.2, 1.25] {
+            assert_pose(remote.sample(time), time * 10.0, time);
+        }
+        // before the oldest snapshot
+        assert_pose(remote.sample(0.5), 10.0, 1.0);
+        assert!(RemotePlayer::default().sample(1.0).is_none());
+    }
+
+    #[test]
+    fn extrapolates_the_position_for_a_while() {
+        let remote = remote_player(&[1.0, 1.1]);
+        // keeps moving, but stays turned the way the newest snapshot is
+        assert_pose(remote.sample(1.15), 11.5, 1.1);
+        // then stops
+        let stop = (1.1 + MAX_EXTRAPOLATION) * 10.0;
+        assert_pose(remote.sample(1.1 + MAX_EXTRAPOLATION), stop, 1.1);
+        assert_pose(remote.sample(5.0), stop, 1.1);
+
+        // a single snapshot has nothing to extrapolate from
+        assert_pose(remote_player(&[1.0]).sample(2.0), 10.0, 1.0);
+    }
+
+    #[test]
+    fn out_of_order_snapshots_are_dropped() {
+        let mut remote = remote_player(&[1.0, 1.1]);
+        assert!(!remote.push(snapshot(1.05)));
+        assert!(!remote.push(Snapshot {
+            translation: Vec3::ZERO,
+            ..snapshot(1.1)
+        }));
+        assert!(remote.push(snapshot(1.2)));
+        let times: Vec<f32> = remote.snapshots.iter().map(|s| s.time).collect();
+        assert_eq!(times, [1.0, 1.1, 1.2]);
+        assert_pose(remote.sample(1.1), 11.0, 1.1);
+    }
+
+    #[test]
+    fn trim_keeps_the_snapshots_still_needed() {
+        let mut remote = remote_player(&[1.0, 1.1, 1.2, 1.3, 1.4]);
+        // the snapshot before the time is still interpolated from
+        remote.trim(1.25);
+        let times: Vec<f32> = remote.snapshots.iter().map(|s| s.time).collect();
+        assert_eq!(times, [1.2, 1.3, 1.4]);
+        assert_pose(remote.sample(1.25), 12.5, 1.25);
+
+        // extrapolating needs the newest two
+        remote.trim(10.0);
+        assert_eq!(remote.snapshots.len(), 2);
+
+        let mut remote = remote_player(&[]);
+        for i in 0..MAX_SNAPSHOTS * 2 {
+            remote.push(snapshot(i as f32));
+        }
+        remote.trim(0.0);
+        assert_eq!(remote.snapshots.len(), MAX_SNAPSHOTS);
+        assert_eq!(remote.snapshots[0].time, MAX_SNAPSHOTS as f32);
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{HashMap, VecDeque};
+
+use ambient_api::{
+    components::core::transform::{rotation, translation},
+    prelude::*,
+};
+
+// furthest back a punch is resolved. Clients lagging further behind than this have to lead
+// their punches by the rest, and nobody gets hit by a punch from further in the past. The
+// history keeps this much of every player, however many ticks that takes at the tick rate the
+// server runs at.
+const MAX_REWIND: f32 = 0.25; // seconds
+
+/// A player's transform at the end of a server tick
+#[derive(Debug, Clone, Copy)]
+struct TransformSample {
+    time: f32,
+    translation: Vec3,
+    rotation: Quat,
+}
+
+/// How far back to resolve an action the client took while showing the other players at
+/// `view_time`, the server time of the snapshots it interpolated them between. `server_time` is
+/// the time of the last tick the server replicated. Clamped to `MAX_REWIND`, and actions claiming
+/// a view ahead of the server aren't rewound at all.
+pub fn rewind_for(view_time: f32, server_time: f32) -> f32 {
+    let rewind = server_time - view_time;
+    if rewind.is_finite() {
+        rewind.clamp(0.0, MAX_REWIND)
+    } else {
+        0.0
+    }
+}
+
+/// The transforms of every player over the last ticks, to resolve hits against the world the
+/// way the attacker saw it. The other players show up on a client interpolated between snapshots
+/// that were already a trip over the network old when they arrived, so the client aims at where
+/// they were a while ago rather than where the server has them now.
+#[derive(Default)]
+pub struct TransformHistory {
+    players: HashMap<EntityId, VecDeque<TransformSample>>,
+}
+
+impl TransformHistory {
+    /// Records the transforms of `player_ids` at the end of the tick at `time`, forgetting the
+    /// players that are gone
+    pub fn record(&mut self, time: f32, player_ids: &[EntityId]) {
+        self.players.retain(|id, _| player_ids.contains(id));
+        for &player_id in player_ids {
+            let (Some(player_translation), Some(player_rotation)) = (
+                entity::get_component(player_id, translation()),
+                entity::get_component(player_id, rotation()),
+            ) else {
+                continue;
+            };
+            self.push(
+                player_id,
+                TransformSample {
+                    time,
+                    translation: player_translation,
+                    rotation: player_rotation,
+                },
+            );
+        }
+    }
+
+    /// Adds the newest sample of a player, dropping the ones no rewind reaches anymore. The
+    /// sample just before `MAX_REWIND` stays, the furthest rewind interpolates from it.
+    fn push(&mut self, player_id: EntityId, sample: TransformSample) {
+        let samples = self.players.entry(player_id).or_default();
+        samples.push_back(sample);
+        let oldest_needed = sample.time - MAX_REWIND;
+        while samples.len() > 1 && samples[1].time <= oldest_needed {
+            samples.pop_front();
+        }
+    }
+
+    /// Translation and rotation of a player at `time`, interpolated between the ticks around
+    /// it. Times before the history start at its oldest transform, `None` if `time` is after the
+    /// last recorded tick and the current transform applies.
+    pub fn transform_at(&self, player_id: EntityId, time: f32) -> Option<(Vec3, Quat)> {
+        let samples = self.players.get(&player_id)?;
+        let next = samples.iter().position(|sample| sample.time >= time)?;
+        if next == 0 {
+            let oldest = samples[0];
+            return Some((oldest.translation, oldest.rotation));
+        }
+
+        let (from, to) = (&samples[next - 1], &samples[next]);
+        let t = (time - from.time) / (to.time - from.time);
+        Some((
+            from.translation.lerp(to.translation, t),
+            from.rotation.slerp(to.rotation, t),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: f32 = 1.0 / 60.0;
+
+    fn sample(time: f32, x: f32, angle: f32) -> TransformSample {
+        TransformSample {
+            time,
+            translation: Vec3::X * x,
+            rotation: Quat::from_rotation_z(angle),
+        }
+    }
+
+    /// A player moving along x at 10 units per second and turning at 1 radian per second,
+    /// recorded every tick for a second
+    fn history() -> (TransformHistory, EntityId) {
+        let player_id = EntityId::null();
+        let mut history = TransformHistory::default();
+        for tick in 1..=60 {
+            let time = tick as f32 * TICK;
+            history.push(player_id, sample(time, time * 10.0, time));
+        }
+        (history, player_id)
+    }
+
+    fn assert_transform(transform: Option<(Vec3, Quat)>, x: f32, angle: f32) {
+        let (translation, rotation) = transform.unwrap();
+        assert!((translation - Vec3::X * x).length() < 1e-3, "{translation}");
+        assert!(
+            rotation.angle_between(Quat::from_rotation_z(angle)) < 1e-3,
+            "{rotation}"
+        );
+    }
+
+    #[test]
+    fn exact_samples_and_interpolation() {
+        let (history, player_id) = history();
+        assert_transform(history.transform_at(player_id, 1.0), 10.0, 1.0);
+        assert_transform(
+            history.transform_at(player_id, 50.0 * TICK),
+            50.0 * TICK * 10.0,
+            50.0 * TICK,
+        );
+        let between = 50.5 * TICK;
+        assert_transform(
+            history.transform_at(player_id, between),
+            between * 10.0,
+            between,
+        );
+    }
+
+    #[test]
+    fn times_outside_the_history() {
+        let (history, player_id) = history();
+        // the current transform applies to the future
+        assert!(history.transform_at(player_id, 1.0 + TICK).is_none());
+        // further back than the history goes starts at its oldest sample
+        let oldest = history.players[&player_id][0].time;
+        assert_transform(history.transform_at(player_id, 0.0), oldest * 10.0, oldest);
+        assert_transform(history.transform_at(player_id, -1e6), oldest * 10.0, oldest);
+        // players without a history are wherever they are now
+        assert!(TransformHistory::default()
+            .transform_at(player_id, 0.5)
+            .is_none());
+    }
+
+    #[test]
+    fn history_covers_the_max_rewind_at_any_tick_rate() {
+        for ticks_per_second in [20, 30, 60, 144, 240] {
+            let dt = 1.0 / ticks_per_second as f32;
+            let player_id = EntityId::null();
+            let mut history = TransformHistory::default();
+            let mut time = 0.0;
+            for tick in 0..ticks_per_second * 2 {
+                time = tick as f32 * dt;
+                history.push(player_id, sample(time, time * 10.0, time));
+            }
+
+            let samples = &history.players[&player_id];
+            assert!(
+                samples[0].time <= time - MAX_REWIND,
+                "{ticks_per_second} ticks per second"
+            );
+            assert!(
+                samples[1].time > time - MAX_REWIND,
+                "{ticks_per_second} ticks per second"
+            );
+            let rewound = time - MAX_REWIND;
+            assert_transform(
+                history.transform_at(player_id, rewound),
+                rewound * 10.0,
+                rewound,
+            );
+        }
+    }
+
+    #[test]
+    fn rewind_is_clamped() {
+        let cases = [
+            (0.9, 1.0, 0.1),
+            (1.0, 1.0, 0.0),
+            (1.0 - MAX_REWIND, 1.0, MAX_REWIND),
+            // lagging further behind than the max rewind, or a client that never saw a snapshot
+            (0.5, 1.0, MAX_REWIND),
+            (0.0, 100.0, MAX_REWIND),
+            // claiming to see the future
+            (1.5, 1.0, 0.0),
+            (f32::NAN, 1.0, 0.0),
+            (f32::NEG_INFINITY, 1.0, 0.0),
+        ];
+        for (view_time, server_time, expected) in cases {
+            let rewind = rewind_for(view_time, server_time);
+            assert!(
+                (rewind - expected).abs() < 1e-6,
+                "{view_time} {server_time}: {rewind}"
+            );
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use ambient_api::prelude::{Quat, Vec2, Vec3};
+use core::f32::consts::FRAC_PI_2;
+
+// the movement math shared by the server, which simulates every player, and the client, which
+// predicts its own player with it. All speeds are per second and get scaled by the step time, so
+// the simulation does not depend on the server tick rate or the client frame rate.
+pub const WALK_SPEED: f32 = 6.0; // world units per second
+pub const DASH_SPEED: f32 = 48.0; // world units per second
+pub const GRAVITY: f32 = 30.0; // world units per second squared
+pub const DEFAULT_JUMP_HEIGHT: f32 = 4.0; // world units
+pub const LOOK_SENSITIVITY: f32 = 0.01; // radians per pixel of mouse movement
+pub const DASH_COOLDOWN: f32 = 0.5; // seconds between the end of a dash and the next one
+
+// keep the view a bit off straight up and down to avoid gimbal lock
+const MIN_VIEW_ANGLE: f32 = -FRAC_PI_2 + 0.1;
+const MAX_VIEW_ANGLE: f32 = FRAC_PI_2 - 0.1;
+
+const WORLD_FRONT: Vec3 = Vec3::X;
+const WORLD_RIGHT: Vec3 = Vec3::Y;
+
+/// Initial upwards velocity needed to reach `jump_height` under `GRAVITY`
+pub fn jump_velocity(jump_height: f32) -> f32 {
+    (2.0 * GRAVITY * jump_height).sqrt()
+}
+
+/// The input of a player for one movement step
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MovementInput {
+    pub direction: Vec2,
+    pub is_dashing: bool,
+    pub is_jumping: bool,
+    /// mouse movement of the step in pixels, see `MovementState::turn`. Input turning at a rate,
+    /// like a stick held to the side, has to be scaled by the length of the step to get there.
+    pub look: Vec2,
+}
+
+/// The part of a player's state the movement steps advance. The position isn't part of it, the
+/// server moves it through the character controller and the client on its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovementState {
+    pub rotation: Quat,
+    /// accumulated vertical look angle, the view clamps it in `view_vertical_rotation`
+    pub vertical_rotation_angle: f32,
+    pub vertical_velocity: f32,
+    pub is_grounded: bool,
+}
+
+impl MovementState {
+    /// Turns the player and its view by `look`, the mouse movement of every frame summed up.
+    /// The mouse moving the same distance turns the same no matter how many frames it took or
+    /// how the frames get split into input messages.
+    pub fn turn(&mut self, look: Vec2) {
+        self.rotation *= Quat::from_rotation_z(look.x * LOOK_SENSITIVITY);
+        self.vertical_rotation_angle += look.y * LOOK_SENSITIVITY;
+    }
+
+    pub fn view_vertical_rotation(&self) -> Quat {
+        Quat::from_rotation_y(
+            self.vertical_rotation_angle
+                .clamp(MIN_VIEW_ANGLE, MAX_VIEW_ANGLE),
+        )
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        self.rotation * WORLD_FRONT
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.rotation * WORLD_RIGHT
+    }
+
+    /// Horizontal direction the input moves the player in. Not normalized, moving diagonally is
+    /// faster.
+    pub fn move_direction(&self, input: &MovementInput) -> Vec3 {
+        let mut direction = Vec3::ZERO;
+        if input.direction.x == 1.0 {
+            direction += self.forward();
+        }
+        if input.direction.x == -1.0 {
+            direction -= self.forward();
+        }
+        if input.direction.y == -1.0 {
+            direction -= self.right();
+        }
+        if input.direction.y == 1.0 {
+            direction += self.right();
+        }
+        direction
+    }
+
+    /// Velocity the input moves the player with this step, in world units per second. A jump
+    /// request is only honoured when standing on something, after that gravity takes over until
+    /// `collide` reports the ground again.
+    pub fn velocity(
+        &mut self,
+        input: &MovementInput,
+        can_dash: bool,
+        jump_height: f32,
+        dt: f32,
+    ) -> Vec3 {
+        if input.is_jumping && self.is_grounded {
+            self.vertical_velocity = jump_velocity(jump_height);
+        }
+        // moving at the average vertical velocity of the step falls exactly as far as gravity
+        // pulls in that time, so jumps and falls come out the same at any step length
+        let start_vertical_velocity = self.vertical_velocity;
+        self.vertical_velocity -= GRAVITY * dt;
+        let vertical_velocity = (start_vertical_velocity + self.vertical_velocity) / 2.0;
+
+        let speed = if input.is_dashing && can_dash {
+            DASH_SPEED
+        } else {
+            WALK_SPEED
+        };
+        self.move_direction(input) * speed + Vec3::Z * vertical_velocity
+    }
+
+    /// Applies the collisions of the last move, `down` if the player stands on something and
+    /// `up` if it hit something above
+    pub fn collide(&mut self, down: bool, up: bool) {
+        if (down && self.vertical_velocity < 0.0) || (up && self.vertical_velocity > 0.0) {
+            self.vertical_velocity = 0.0;
+        }
+        self.is_grounded = down;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ambient_api::prelude::{vec2, vec3};
+    use std::f32::consts::PI;
+
+    const FORWARD: Vec2 = Vec2::X;
+    const RIGHT: Vec2 = Vec2::Y;
+
+    fn standing() -> MovementState {
+        MovementState {
+            rotation: Quat::IDENTITY,
+            vertical_rotation_angle: 0.0,
+            vertical_velocity: 0.0,
+            is_grounded: true,
+        }
+    }
+
+    fn input(direction: Vec2) -> MovementInput {
+        MovementInput {
+            direction,
+            ..MovementInput::default()
+        }
+    }
+
+    /// Steps a player starting out standing at the origin on flat ground at height 0, `dt`
+    /// seconds at a time for `steps` steps. `input_at` gives the input of the step starting at
+    /// the time passed. Returns where the player is after every step.
+    fn simulate(
+        state: &mut MovementState,
+        dt: f32,
+        steps: usize,
+        mut input_at: impl FnMut(f32) -> MovementInput,
+    ) -> Vec<Vec3> {
+        let mut position = Vec3::ZERO;
+        (0..steps)
+            .map(|step| {
+                let input = input_at(step as f32 * dt);
+                state.turn(input.look);
+                position += state.velocity(&input, true, DEFAULT_JUMP_HEIGHT, dt) * dt;
+                let landed = position.z <= 0.0;
+                position.z = position.z.max(0.0);
+                state.collide(landed, false);
+                position
+            })
+            .collect()
+    }
+
+    fn assert_near(a: Vec3, b: Vec3, context: &str) {
+        assert!(a.distance(b) < 1e-3, "{context}: {a} != {b}");
+    }
+
+    #[test]
+    fn walks_and_dashes_at_their_speed() {
+        let mut state = standing();
+        let path = simulate(&mut state, 1.0 / 60.0, 60, |_| input(FORWARD + RIGHT));
+        assert_near(path[59], vec3(WALK_SPEED, WALK_SPEED, 0.0), "diagonal walk");
+
+        let dash = MovementInput {
+            is_dashing: true,
+            ..input(FORWARD)
+        };
+        let mut state = standing();
+        let path = simulate(&mut state, 1.0 / 60.0, 30, |_| dash);
+        assert_near(path[29], vec3(DASH_SPEED / 2.0, 0.0, 0.0), "dash");
+
+        // the punch keeps the player from dashing, not from walking
+        let mut state = standing();
+        let velocity = state.velocity(&dash, false, DEFAULT_JUMP_HEIGHT, 1.0 / 60.0);
+        assert_eq!(velocity.x, WALK_SPEED);
+    }
+
+    #[test]
+    fn walks_where_the_player_faces() {
+        let mut state = standing();
+        // a quarter turn to the right
+        state.turn(vec2(FRAC_PI_2 / LOOK_SENSITIVITY, 0.0));
+        let path = simulate(&mut state, 1.0 / 60.0, 60, |_| input(FORWARD));
+        assert_near(
+            path[59],
+            vec3(0.0, WALK_SPEED, 0.0),
+            "forward after turning",
+        );
+    }
+
+    #[test]
+    fn jumps_to_the_jump_height_and_lands() {
+        let dt = 1.0 / 60.0;
+        let mut state = standing();
+        let mut jumped = false;
+        let path = simulate(&mut state, dt, 120, |_| MovementInput {
+            is_jumping: !std::mem::replace(&mut jumped, true),
+            ..MovementInput::default()
+        });
+
+        let apex = path.iter().map(|position| position.z).fold(0.0, f32::max);
+        assert!((apex - DEFAULT_JUMP_HEIGHT).abs() < 0.01, "apex {apex}");
+        let air_time = 2.0 * jump_velocity(DEFAULT_JUMP_HEIGHT) / GRAVITY;
+        let landing = path.iter().skip(1).position(|position| position.z == 0.0);
+        let landing_time = (landing.unwrap() + 2) as f32 * dt;
+        assert!(
+            (air_time..air_time + dt).contains(&landing_time),
+            "landed after {landing_time}s instead of {air_time}s"
+        );
+        assert!(state.is_grounded);
+        assert_eq!(state.vertical_velocity, 0.0);
+        assert_eq!(path[119], Vec3::ZERO);
+    }
+
+    #[test]
+    fn jumps_only_from_the_ground() {
+        let jump = MovementInput {
+            is_jumping: true,
+            ..MovementInput::default()
+        };
+        let mut state = MovementState {
+            is_grounded: false,
+            ..standing()
+        };
+        state.velocity(&jump, true, DEFAULT_JUMP_HEIGHT, 1.0 / 60.0);
+        assert!(state.vertical_velocity < 0.0);
+
+        // holding jump only jumps again once back on the ground
+        let mut state = standing();
+        simulate(&mut state, 1.0 / 60.0, 30, |_| jump);
+        let expected = jump_velocity(DEFAULT_JUMP_HEIGHT) - GRAVITY * 0.5;
+        assert!((state.vertical_velocity - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn falls_with_gravity_and_stops_on_what_it_hits() {
+        let mut state = MovementState {
+            is_grounded: false,
+            ..standing()
+        };
+        let mut fall = Vec3::ZERO;
+        for _ in 0..40 {
+            fall +=
+                state.velocity(&MovementInput::default(), true, DEFAULT_JUMP_HEIGHT, 0.025) * 0.025;
+        }
+        assert_near(fall, vec3(0.0, 0.0, -GRAVITY / 2.0), "fall for a second");
+
+        // hitting a ceiling stops going up, but not falling
+        state.vertical_velocity = 5.0;
+        state.collide(false, true);
+        assert_eq!(state.vertical_velocity, 0.0);
+        state.vertical_velocity = -5.0;
+        state.collide(false, true);
+        assert_eq!(state.vertical_velocity, -5.0);
+        assert!(!state.is_grounded);
+    }
+
+    #[test]
+    fn view_stays_off_straight_up_and_down() {
+        let mut state = standing();
+        state.turn(vec2(0.0, 1000.0));
+        assert_near(
+            state.view_vertical_rotation() * Vec3::X,
+            Quat::from_rotation_y(MAX_VIEW_ANGLE) * Vec3::X,
+            "clamped view",
+        );
+    }
+
+    #[test]
+    fn tick_rate_does_not_change_the_outcome() {
+        // jump while walking forward, then turn around, dash back and start strafing in the air.
+        // The jump lasts about a second, so the player lands while strafing.
+        let script = |dt: f32| {
+            let mut jumped = false;
+            move |time: f32| {
+                // the middle of the step, so steps starting right on a boundary don't round to
+                // either side of it
+                let time = time + dt / 2.0;
+                let mut input = MovementInput {
+                    is_jumping: !std::mem::replace(&mut jumped, true),
+                    ..MovementInput::default()
+                };
+                if time < 0.25 {
+                    input.direction = FORWARD;
+                } else if time < 0.5 {
+                    // half around in a quarter second
+                    input.look = vec2(4.0 * PI / LOOK_SENSITIVITY * dt, 0.0);
+                } else if time < 0.75 {
+                    input.direction = FORWARD;
+                    input.is_dashing = true;
+                } else {
+                    input.direction = RIGHT;
+                }
+                input
+            }
+        };
+
+        // where the player is every quarter second, which every tick rate has a step end at
+        let sample = |ticks_per_second: usize| {
+            let dt = 1.0 / ticks_per_second as f32;
+            let mut state = standing();
+            let path = simulate(&mut state, dt, ticks_per_second * 3 / 2, script(dt));
+            let samples: Vec<Vec3> = path
+                .iter()
+                .skip(ticks_per_second / 4 - 1)
+                .step_by(ticks_per_second / 4)
+                .copied()
+                .collect();
+            (samples, state)
+        };
+        let (expected, expected_state) = sample(40);
+        assert_eq!(expected.len(), 6);
+        // the dash went back the way the player came in mid-air, the landing came after it
+        assert!(expected[1].z > 0.0 && expected[3].z > 0.0);
+        assert!(expected[2].x < expected[1].x);
+        assert_eq!(expected[5].z, 0.0);
+        for ticks_per_second in [60, 120, 240] {
+            let (samples, state) = sample(ticks_per_second);
+            for (time, (&sample, &expected)) in samples.iter().zip(&expected).enumerate() {
+                assert_near(
+                    sample,
+                    expected,
+                    &format!(
+                        "{ticks_per_second} ticks per second, {}s",
+                        (time + 1) as f32 / 4.0
+                    ),
+                );
+            }
+            assert_near(state.forward(), expected_state.forward(), "facing");
+            assert_eq!(state.is_grounded, expected_state.is_grounded);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// debug layer to try the client/server split under bad network conditions on one machine, where
+// messages otherwise arrive instantly. Only the input from the clients to the server goes
+// through it: the clients simulate it on sending, the server on receiving, so it also covers
+// clients that don't simulate anything. The conditions of both come from the debug config of
+// the server, `network_simulation_enabled` switches them on and off while the game runs, and
+// they only ever apply to debug builds.
+
+use serde::{Deserialize, Serialize};
+
+// the same seed every run, so a simulated session loses and delays the same messages
+const SIMULATION_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// how much longer a reordered message is held back than it would have been, enough to let a
+// few later messages overtake it
+const REORDER_DELAY: f32 = 0.1; // seconds
+
+/// Network conditions to simulate, one way
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConditions {
+    /// seconds every message is delayed by
+    pub latency: f32,
+    /// up to how many seconds the delay randomly varies by, in both directions
+    pub jitter: f32,
+    /// chance of an unreliable message getting lost, from 0 to 1
+    pub loss: f32,
+    /// chance of an unreliable message arriving after later ones, from 0 to 1
+    pub reorder: f32,
+}
+
+impl NetworkConditions {
+    pub const PERFECT: NetworkConditions = NetworkConditions {
+        latency: 0.0,
+        jitter: 0.0,
+        loss: 0.0,
+        reorder: 0.0,
+    };
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self::PERFECT
+    }
+}
+
+/// Simulates `conditions` with `link` for the messages sent from now on, the ones on their way
+/// arrive as planned. A link without anything to simulate is dropped once it delivered every
+/// message, after that messages should be delivered right away again.
+pub fn simulate<T>(link: &mut Option<SimulatedLink<T>>, conditions: NetworkConditions) {
+    match link {
+        Some(current)
+            if conditions == NetworkConditions::PERFECT && current.in_flight.is_empty() =>
+        {
+            *link = None;
+        }
+        Some(current) => current.conditions = conditions,
+        None => *link = SimulatedLink::new(conditions),
+    }
+}
+
+/// A message on its way through a `SimulatedLink`
+struct InFlight<T> {
+    arrival: f32,
+    message: T,
+}
+
+/// Holds messages back as if they were sent over a network with the given conditions. Messages
+/// go in with `send_unreliable` or `send_reliable` and come out of `receive` once they arrived.
+/// Reliable messages are never lost and arrive in the order they were sent, unreliable ones
+/// may get lost or overtaken by later ones.
+pub struct SimulatedLink<T> {
+    conditions: NetworkConditions,
+    clock: f32,
+    rng: u64,
+    /// arrival of the last reliable message, the next one can't arrive before
+    last_reliable_arrival: f32,
+    /// in the order of sending
+    in_flight: Vec<InFlight<T>>,
+}
+
+impl<T> SimulatedLink<T> {
+    /// A link simulating `conditions`, or `None` if there is nothing to simulate and messages
+    /// should be delivered right away
+    pub fn new(conditions: NetworkConditions) -> Option<Self> {
+        if !cfg!(debug_assertions) || conditions == NetworkConditions::PERFECT {
+            return None;
+        }
+        Some(Self::with_conditions(conditions))
+    }
+
+    fn with_conditions(conditions: NetworkConditions) -> Self {
+        Self {
+            conditions,
+            clock: 0.0,
+            rng: SIMULATION_SEED,
+            last_reliable_arrival: 0.0,
+            in_flight: Vec::new(),
+        }
+    }
+
+    pub fn send_unreliable(&mut self, message: T) {
+        if self.random() < self.conditions.loss {
+            return;
+        }
+        let mut arrival = self.clock + self.delay();
+        if self.random() < self.conditions.reorder {
+            arrival += REORDER_DELAY;
+        }
+        self.in_flight.push(InFlight { arrival, message });
+    }
+
+    pub fn send_reliable(&mut self, message: T) {
+        let arrival = (self.clock + self.delay()).max(self.last_reliable_arrival);
+        self.last_reliable_arrival = arrival;
+        self.in_flight.push(InFlight { arrival, message });
+    }
+
+    /// Advances the link by `dt` seconds and returns the messages that arrived in the meantime,
+    /// in the order they arrived
+    pub fn receive(&mut self, dt: f32) -> Vec<T> {
+        self.clock += dt;
+        let clock = self.clock;
+        let (mut arrived, in_flight): (Vec<_>, Vec<_>) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|in_flight| in_flight.arrival <= clock);
+        self.in_flight = in_flight;
+        // stable, messages arriving at the same time stay in the order they were sent
+        arrived.sort_by(|a, b| a.arrival.total_cmp(&b.arrival));
+        arrived
+            .into_iter()
+            .map(|in_flight| in_flight.message)
+            .collect()
+    }
+
+    fn delay(&mut self) -> f32 {
+        let jitter = (self.random() * 2.0 - 1.0) * self.conditions.jitter;
+        (self.conditions.latency + jitter).max(0.0)
+    }
+
+    /// Uniformly distributed between 0 and 1, from a xorshift64* generator
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let bits = self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40;
+        bits as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: f32 = 1.0 / 60.0;
+
+    /// Sends `count` numbered messages one per tick, then waits for all of them to arrive.
+    /// Returns them in the order they arrived.
+    fn transmit(conditions: NetworkConditions, count: u32, reliable: bool) -> Vec<u32> {
+        let mut link = SimulatedLink::with_conditions(conditions);
+        let mut arrived = vec![];
+        for message in 0..count {
+            if reliable {
+                link.send_reliable(message);
+            } else {
+                link.send_unreliable(message);
+            }
+            arrived.extend(link.receive(TICK));
+        }
+        arrived.extend(link.receive(conditions.latency + conditions.jitter + REORDER_DELAY));
+        assert!(link.in_flight.is_empty());
+        arrived
+    }
+
+    fn out_of_order(arrived: &[u32]) -> usize {
+        arrived.windows(2).filter(|pair| pair[1] < pair[0]).count()
+    }
+
+    const STORMY: NetworkConditions = NetworkConditions {
+        latency: 0.1,
+        jitter: 0.08,
+        loss: 0.5,
+        reorder: 0.5,
+    };
+
+    #[test]
+    fn reliable_messages_all_arrive_in_order() {
+        let arrived = transmit(STORMY, 2000, true);
+        assert_eq!(arrived, (0..2000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn unreliable_messages_get_lost_at_the_loss_rate() {
+        for loss in [0.05, 0.2, 0.5] {
+            let conditions = NetworkConditions {
+                loss,
+                ..NetworkConditions::PERFECT
+            };
+            let arrived = transmit(conditions, 10_000, false);
+            let lost = 1.0 - arrived.len() as f32 / 10_000.0;
+            assert!((lost - loss).abs() < 0.02, "{lost} lost instead of {loss}");
+            assert_eq!(out_of_order(&arrived), 0);
+        }
+    }
+
+    #[test]
+    fn unreliable_messages_get_reordered() {
+        let conditions = NetworkConditions {
+            latency: 0.05,
+            reorder: 0.1,
+            ..NetworkConditions::PERFECT
+        };
+        let arrived = transmit(conditions, 10_000, false);
+        assert_eq!(arrived.len(), 10_000);
+        // a held back message gets overtaken by the next few, but only counts once
+        let reordered = out_of_order(&arrived) as f32 / 10_000.0;
+        assert!((reordered - 0.1).abs() < 0.02, "{reordered} reordered");
+
+        // jitter alone reorders messages sent close together as well
+        let conditions = NetworkConditions {
+            latency: 0.05,
+            jitter: 0.05,
+            ..NetworkConditions::PERFECT
+        };
+        assert!(out_of_order(&transmit(conditions, 1000, false)) > 0);
+    }
+
+    #[test]
+    fn messages_arrive_after_the_latency() {
+        let conditions = NetworkConditions {
+            latency: 0.1,
+            ..NetworkConditions::PERFECT
+        };
+        let mut link = SimulatedLink::with_conditions(conditions);
+        link.send_unreliable(1);
+        link.send_reliable(2);
+        assert!(link.receive(0.09).is_empty());
+        assert_eq!(link.receive(0.02), [1, 2]);
+    }
+
+    #[test]
+    fn the_same_seed_simulates_the_same_network() {
+        let first = transmit(STORMY, 1000, false);
+        assert_eq!(first, transmit(STORMY, 1000, false));
+        assert!(first.len() < 1000 && out_of_order(&first) > 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn simulation_switches_on_and_off() {
+        let mut link = None;
+        simulate(&mut link, NetworkConditions::PERFECT);
+        assert!(link.is_none());
+
+        simulate(&mut link, STORMY);
+        let current = link.as_mut().unwrap();
+        current.send_reliable(1);
+        // messages on their way still arrive once the simulation is off
+        simulate(&mut link, NetworkConditions::PERFECT);
+        let current = link.as_mut().unwrap();
+        current.send_reliable(2);
+        assert_eq!(current.receive(0.0), []);
+        assert_eq!(current.receive(1.0), [1, 2]);
+        simulate(&mut link, NetworkConditions::PERFECT);
+        assert!(link.is_none());
+    }
+}
@@ -1,200 +1,644 @@
-use crate::components::player_animation_state;
+use crate::components::{
+    animation_blend_depth, animation_blend_duration, animation_blend_elapsed,
+    animation_blend_target, animation_debug_clock, animation_history_events,
+    animation_history_from_states, animation_history_times, animation_history_to_states,
+    animation_layer_blend_node, animation_layer_fade_rate, animation_layer_weight,
+    animation_layers, animation_playback_rate, animation_root_node, animation_state_elapsed,
+    locomotion_blend_nodes, locomotion_clip_nodes, player_animation_elapsed,
+    player_animation_state, player_animation_states,
+};
 use ambient_api::{
-    animation::{AnimationPlayer, PlayClipFromUrlNode},
-    components::core::animation::apply_animation_player,
+    animation::{AnimationNode, AnimationPlayer, BlendNode, PlayClipFromUrlNode},
+    components::core::{
+        animation::{apply_animation_player, speed as playback_speed},
+        ecs::{children, parent},
+    },
     prelude::*,
 };
 
-use num_derive::FromPrimitive;
-// state machine from:
-// https://play.rust-lang.org/?version=stable&mode=debug&edition=2015&gist=ee3e4df093c136ced7b394dc7ffb78e1
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, FromPrimitive)]
-pub enum PlayerAnimationState {
-    Idle,
-    Walking,
-    Dashing,
-    Punching,
-    Jumping,
+use crate::animation_state_machine::{
+    state_machine, AnimationLayer, AnimationState, AnimationStateError, StateMotion,
+};
+pub use crate::animation_state_machine::{PlayerAnimationEvent, PlayerAnimationState};
+use crate::blend_space::BlendSpace;
+
+// records the transition history and replicates the time into every state for the debug overlay
+// of the clients, this costs a networked update per mech and frame so it's off in release builds
+const ANIMATION_DEBUG: bool = cfg!(debug_assertions);
+// transitions kept in the history of each mech
+const ANIMATION_HISTORY_LENGTH: usize = 16;
+// crossfades interrupted by another transition keep playing nested inside the new one, at most
+// this many deep before the oldest ones get cut short
+const MAX_NESTED_BLENDS: u32 = 3;
+
+fn make_clip(clip_path: &str, looping: bool) -> PlayClipFromUrlNode {
+    let clip = PlayClipFromUrlNode::new(asset::url(clip_path).unwrap());
+    clip.looping(looping);
+    clip
+}
+
+fn set_mask_weight(blend: &BlendNode, mask: &[String], weight: f32) {
+    blend.set_mask(mask.iter().map(|bone| (bone.clone(), weight)).collect());
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum PlayerAnimationEvent {
-    Stop,
-    Walk,
-    Dash,
-    Punch,
-    Jump,
+/// Takes an animation node out of the blend node it is an input of, if any, so despawning that
+/// blend node leaves it alone
+fn detach_node(node: EntityId) {
+    let Some(parent_id) = entity::get_component(node, parent()) else { return; };
+    entity::mutate_component(parent_id, children(), |inputs| {
+        inputs.retain(|&id| id != node)
+    });
+    entity::remove_component(node, parent());
 }
 
-fn lookup_clip_path(animation_state: PlayerAnimationState) -> &'static str {
-    return match animation_state {
-        PlayerAnimationState::Idle => "assets/mecha.glb/animations/idle_2.anim",
-        PlayerAnimationState::Walking => "assets/mecha.glb/animations/walk_5.anim",
-        PlayerAnimationState::Dashing => "assets/mecha.glb/animations/dash_0.anim",
-        PlayerAnimationState::Punching => "assets/mecha.glb/animations/punch_4.anim",
-        PlayerAnimationState::Jumping => "assets/mecha.glb/animations/dash_0.anim",
-    };
+/// Despawns an animation node together with all of its inputs
+fn despawn_node(node: EntityId) {
+    for input in entity::get_component(node, children()).unwrap_or_default() {
+        despawn_node(input);
+    }
+    entity::despawn(node);
+}
+
+/// Makes `inputs` the inputs of an existing blend node, in place of the ones it had
+fn set_blend_inputs(blend: EntityId, inputs: [EntityId; 2]) {
+    for input in inputs {
+        entity::add_component(input, parent(), blend);
+    }
+    entity::set_component(blend, children(), inputs.to_vec());
 }
 
+/// Number of animation nodes in the tree below `node`, `node` included
+fn tree_size(node: EntityId) -> usize {
+    1 + entity::get_component(node, children())
+        .unwrap_or_default()
+        .into_iter()
+        .map(tree_size)
+        .sum::<usize>()
+}
+
+/// Upper bound of the animation nodes a mech plays at once. Every layer plays up to
+/// `MAX_NESTED_BLENDS` interrupted crossfades plus the current one, each adding a blend node and
+/// the motion it fades in on top of the motion it started from, and all layers but the base one
+/// add the blend node putting them on top of the layers below.
+fn max_tree_size() -> usize {
+    let crossfades = MAX_NESTED_BLENDS as usize + 1;
+    state_machine()
+        .layers()
+        .iter()
+        .map(|layer| {
+            let motion_size = layer
+                .states()
+                .iter()
+                .map(|state| match &state.motion {
+                    StateMotion::Clip(_) => 1,
+                    // the speed samples and strafe clips, the speed chain and the strafe blends
+                    StateMotion::BlendSpace(blend_space) => 2 * blend_space.samples.len() + 3,
+                    StateMotion::Empty => 0,
+                })
+                .max()
+                .unwrap_or_default();
+            (crossfades + 1) * motion_size + crossfades + 1
+        })
+        .sum()
+}
+
+/// Appends to a history, dropping the oldest entries beyond `ANIMATION_HISTORY_LENGTH`
+fn push_history<T>(history: &mut Vec<T>, value: T) {
+    history.push(value);
+    let overflow = history.len().saturating_sub(ANIMATION_HISTORY_LENGTH);
+    history.drain(..overflow);
+}
+
+/// Drives the animation player of a mech from the animation state machine.
+///
+/// Every layer of the state machine gets an entity in `animation_layers` holding the state of
+/// that layer, the components below all live on those layer entities. The current states are
+/// also replicated to the clients in `player_animation_states` on the mech, one per layer. The
+/// animation player plays the base layer with every masked layer blended on top of it by a blend
+/// node (`animation_layer_blend_node`), whose mask weights follow `animation_layer_weight`. A
+/// masked layer fades out when it enters a state without motion and back in when it leaves it.
+///
+/// Transitions crossfade from whatever the layer currently plays (`animation_root_node`) into
+/// the new clip with a blend node. While a blend is running `animation_blend_target` holds the
+/// clip being faded in, once the blend finished that clip becomes the root node on its own and
+/// the nodes it faded out of get despawned, as does everything a layer played before switching to
+/// a new clip without blending. A transition interrupting a running blend nests that blend inside
+/// the new one, up to `MAX_NESTED_BLENDS` deep.
+///
+/// States with a blend space play a tree of blend nodes instead of a single clip. Its weights and
+/// playback rates are driven by `set_locomotion` through `locomotion_blend_nodes` (the speed
+/// chain followed by the strafe side and strafe blends) and `locomotion_clip_nodes` (the speed
+/// samples followed by the left and right strafe clips).
+///
+/// `animation_state_elapsed` tracks how long the current state has been playing. When a one-shot
+/// state runs past its duration the controller sends an `AnimationClipFinished` message and feeds
+/// `PlayerAnimationEvent::Finished` into the layer. The elapsed time advances at
+/// `animation_playback_rate`, which follows the moving clips of a blend space so notifies like
+/// footsteps stay on the beat. Every notify the state plays past is sent as an `AnimationNotify`
+/// message and returned from `update` for gameplay on the server.
+///
+/// In debug builds the mech also gets the components behind the clients' animation debug
+/// overlay: `player_animation_elapsed` with the time into the state of every layer, and a ring
+/// buffer of the last `ANIMATION_HISTORY_LENGTH` transitions in `animation_history_times`,
+/// `animation_history_events`, `animation_history_from_states` and `animation_history_to_states`.
+/// The times are on `animation_debug_clock`, which counts the seconds the controller has run.
 pub struct PlayerAnimationController(pub EntityId);
 impl PlayerAnimationController {
     pub fn new(target_id: EntityId) -> Self {
-        let idle_path = lookup_clip_path(PlayerAnimationState::Idle);
-        let idle = PlayClipFromUrlNode::new(asset::url(idle_path).unwrap());
-        let anim_player = AnimationPlayer::new(idle);
-
+        let mut controller = Self(target_id);
+        let machine = state_machine();
+        let initial_states: Vec<u32> = machine
+            .layers()
+            .iter()
+            .map(|layer| layer.encode(layer.initial_state()))
+            .collect();
+        let layers = initial_states
+            .iter()
+            .map(|&initial_state| {
+                Entity::new()
+                    .with(player_animation_state(), initial_state)
+                    .with(animation_state_elapsed(), 0.0)
+                    .with(animation_playback_rate(), 1.0)
+                    .with(animation_layer_weight(), 0.0)
+                    .with(animation_layer_fade_rate(), 0.0)
+                    .spawn()
+            })
+            .collect();
         entity::add_components(
             target_id,
             Entity::new()
-                .with(apply_animation_player(), anim_player.0)
-                .with(player_animation_state(), PlayerAnimationState::Idle as u32),
+                .with(animation_layers(), layers)
+                .with(player_animation_states(), initial_states),
         );
+        if ANIMATION_DEBUG {
+            entity::add_components(
+                target_id,
+                Entity::new()
+                    .with(animation_debug_clock(), 0.0)
+                    .with(
+                        player_animation_elapsed(),
+                        vec![0.0; machine.layers().len()],
+                    )
+                    .with_default(animation_history_times())
+                    .with_default(animation_history_events())
+                    .with_default(animation_history_from_states())
+                    .with_default(animation_history_to_states()),
+            );
+        }
 
-        Self(target_id)
+        controller.reset();
+        controller
     }
 
-    pub fn transition(&mut self, event: PlayerAnimationEvent) -> PlayerAnimationState {
-        let target_entity_id = self.0;
+    /// Puts every layer back into the initial state `new` starts in
+    pub fn reset(&mut self) {
+        for (layer, layer_id) in self.layers() {
+            self.reset_layer(layer_id, layer);
+        }
+    }
 
-        let player_animation_id =
-            entity::get_component(target_entity_id, player_animation_state()).unwrap();
-        let current_state = match num::FromPrimitive::from_u32(player_animation_id) {
-            Some(animation_state) => animation_state,
-            None => {
-                eprintln!("Unkown animation state!!!");
-                PlayerAnimationState::Idle
+    fn reset_layer(&self, layer_id: EntityId, layer: &AnimationLayer) {
+        let initial_state = layer.initial_state();
+        self.enter_state(layer_id, layer, layer.state(initial_state).unwrap(), 0.0);
+        self.set_state(layer_id, layer, initial_state);
+    }
+
+    /// Decodes the current state of a layer from `player_animation_state`
+    fn current_state(
+        &self,
+        layer_id: EntityId,
+        layer: &AnimationLayer,
+    ) -> Result<PlayerAnimationState, AnimationStateError> {
+        layer.decode(entity::get_component(layer_id, player_animation_state()).unwrap())
+    }
+
+    /// Stores the state the layer just entered and replicates it to the clients
+    fn set_state(&self, layer_id: EntityId, layer: &AnimationLayer, state: PlayerAnimationState) {
+        let encoded = layer.encode(state);
+        entity::set_component(layer_id, player_animation_state(), encoded);
+        entity::set_component(layer_id, animation_state_elapsed(), 0.0);
+        entity::set_component(layer_id, animation_playback_rate(), 1.0);
+        entity::mutate_component(self.0, player_animation_states(), |states| {
+            states[layer.id.0 as usize] = encoded;
+        });
+    }
+
+    /// Feeds `event` into every layer
+    pub fn transition(&mut self, event: PlayerAnimationEvent) {
+        for (layer, layer_id) in self.layers() {
+            self.transition_layer(layer_id, layer, event);
+        }
+    }
+
+    fn transition_layer(
+        &mut self,
+        layer_id: EntityId,
+        layer: &AnimationLayer,
+        event: PlayerAnimationEvent,
+    ) -> PlayerAnimationState {
+        let current_state = match self.current_state(layer_id, layer) {
+            Ok(state) => state,
+            Err(error) => {
+                // there's no telling what the layer plays, start it over from a known state
+                eprintln!(
+                    "animation layer {}: {error}, resetting it to its initial state",
+                    layer.name
+                );
+                self.reset_layer(layer_id, layer);
+                layer.initial_state()
             }
         };
 
-        let mut clip_looping = false;
-        let next_state = match (current_state, event) {
-            // Idle
-            (PlayerAnimationState::Idle, PlayerAnimationEvent::Walk) => {
-                clip_looping = true;
-                PlayerAnimationState::Walking
-            }
-            (PlayerAnimationState::Idle, PlayerAnimationEvent::Stop) => PlayerAnimationState::Idle,
+        let step = layer.step(current_state, event);
+        if !step.changed {
+            // return early no state transition
+            return step.state;
+        }
 
-            (PlayerAnimationState::Idle, PlayerAnimationEvent::Dash) => {
-                PlayerAnimationState::Dashing
-            }
+        self.enter_state(layer_id, layer, step.animation_state, step.blend);
+        self.set_state(layer_id, layer, step.state);
+        self.record_transition(layer, current_state, event, step.state);
+        step.state
+    }
 
-            (PlayerAnimationState::Idle, PlayerAnimationEvent::Punch) => {
-                PlayerAnimationState::Punching
-            }
+    /// Adds a transition to the history of the debug overlay, if the mech keeps one
+    fn record_transition(
+        &self,
+        layer: &AnimationLayer,
+        from: PlayerAnimationState,
+        event: PlayerAnimationEvent,
+        to: PlayerAnimationState,
+    ) {
+        let Some(time) = entity::get_component(self.0, animation_debug_clock()) else { return; };
+        entity::mutate_component(self.0, animation_history_times(), |times| {
+            push_history(times, time);
+        });
+        entity::mutate_component(self.0, animation_history_events(), |events| {
+            push_history(events, event.into());
+        });
+        entity::mutate_component(self.0, animation_history_from_states(), |states| {
+            push_history(states, layer.encode(from));
+        });
+        entity::mutate_component(self.0, animation_history_to_states(), |states| {
+            push_history(states, layer.encode(to));
+        });
+    }
 
-            (PlayerAnimationState::Idle, PlayerAnimationEvent::Jump) => {
-                PlayerAnimationState::Jumping
-            }
+    /// Starts playing `animation_state` on the layer, crossfading into it over `blend` seconds
+    fn enter_state(
+        &self,
+        layer_id: EntityId,
+        layer: &AnimationLayer,
+        animation_state: &AnimationState,
+        blend: f32,
+    ) {
+        if let StateMotion::Empty = animation_state.motion {
+            // the last pose keeps playing while the layer fades out
+            self.fade_layer(layer_id, layer, 0.0, blend);
+            return;
+        }
 
-            // Walking
-            (PlayerAnimationState::Walking, PlayerAnimationEvent::Walk) => {
-                clip_looping = true;
-                PlayerAnimationState::Walking
-            }
-            (PlayerAnimationState::Walking, PlayerAnimationEvent::Stop) => {
-                PlayerAnimationState::Idle
-            }
-            (PlayerAnimationState::Walking, PlayerAnimationEvent::Dash) => {
-                PlayerAnimationState::Dashing
-            }
-            (PlayerAnimationState::Walking, PlayerAnimationEvent::Punch) => {
-                PlayerAnimationState::Punching
-            }
+        // a faded out layer has no pose left to crossfade from
+        let weight = entity::get_component(layer_id, animation_layer_weight()).unwrap_or_default();
+        let node = self.make_node(layer_id, animation_state);
+        if blend > 0.0 && weight > 0.0 {
+            self.start_blend(layer_id, node, blend);
+        } else {
+            self.play_immediately(layer_id, node);
+        }
+        self.fade_layer(layer_id, layer, 1.0, blend);
+    }
 
-            (PlayerAnimationState::Walking, PlayerAnimationEvent::Jump) => {
-                PlayerAnimationState::Jumping
-            }
+    /// Advances the current state, a running crossfade and the fade of every layer by `dt`
+    /// seconds. Returns the names of the notifies fired during the update.
+    pub fn update(&mut self, dt: f32) -> Vec<&'static str> {
+        let mut notifies = vec![];
+        for (layer, layer_id) in self.layers() {
+            notifies.extend(self.update_state_time(layer_id, layer, dt));
+            self.update_blend(layer_id, dt);
+            self.update_fade(layer_id, layer, dt);
+        }
+        self.update_debug(dt);
+        notifies
+    }
 
-            //Dashing
-            (PlayerAnimationState::Dashing, PlayerAnimationEvent::Dash) => {
-                PlayerAnimationState::Dashing
-            }
-            (PlayerAnimationState::Dashing, PlayerAnimationEvent::Stop) => {
-                PlayerAnimationState::Idle
-            }
+    /// Advances the debug clock and replicates the time into the state of every layer
+    fn update_debug(&self, dt: f32) {
+        let Some(clock) = entity::get_component(self.0, animation_debug_clock()) else { return; };
+        entity::set_component(self.0, animation_debug_clock(), clock + dt);
+        let elapsed = self
+            .layers()
+            .map(|(_, layer_id)| {
+                entity::get_component(layer_id, animation_state_elapsed()).unwrap_or_default()
+            })
+            .collect();
+        entity::set_component(self.0, player_animation_elapsed(), elapsed);
+    }
 
-            (PlayerAnimationState::Dashing, PlayerAnimationEvent::Walk) => {
-                clip_looping = true;
-                PlayerAnimationState::Walking
-            }
+    fn update_state_time(
+        &mut self,
+        layer_id: EntityId,
+        layer: &'static AnimationLayer,
+        dt: f32,
+    ) -> Vec<&'static str> {
+        let target_entity_id = self.0;
+        // a broken state gets reported and reset by the next transition
+        let Ok(state) = self.current_state(layer_id, layer) else {
+            return vec![];
+        };
+        let previous_elapsed =
+            entity::get_component(layer_id, animation_state_elapsed()).unwrap_or_default();
+        let playback_rate =
+            entity::get_component(layer_id, animation_playback_rate()).unwrap_or(1.0);
+        let elapsed = previous_elapsed + dt * playback_rate;
+        entity::set_component(layer_id, animation_state_elapsed(), elapsed);
+
+        let animation_state = layer.state(state).unwrap();
+        let notifies: Vec<&'static str> = animation_state
+            .notifies_between(previous_elapsed, elapsed)
+            .collect();
+        for &name in &notifies {
+            let notify = messages::AnimationNotify::new(target_entity_id, name.to_string());
+            notify.send_local_broadcast(true);
+            // sounds and particles play on the clients, a missed footstep isn't worth a resend
+            notify.send_client_broadcast_unreliable();
+        }
 
-            (PlayerAnimationState::Dashing, PlayerAnimationEvent::Punch) => {
-                PlayerAnimationState::Punching
-            }
+        // only report the frame the clip crosses its end, a state that stays put on `Finished`
+        // keeps running without finishing again
+        if animation_state.looping
+            || previous_elapsed >= animation_state.duration
+            || elapsed < animation_state.duration
+        {
+            return notifies;
+        }
 
-            (PlayerAnimationState::Dashing, PlayerAnimationEvent::Jump) => {
-                PlayerAnimationState::Jumping
-            }
+        messages::AnimationClipFinished::new(target_entity_id, layer.encode(state))
+            .send_local_broadcast(true);
+        self.transition_layer(layer_id, layer, PlayerAnimationEvent::Finished);
+        notifies
+    }
 
-            //Punching
-            (PlayerAnimationState::Punching, PlayerAnimationEvent::Stop) => {
-                PlayerAnimationState::Idle
-            }
+    fn update_blend(&mut self, layer_id: EntityId, dt: f32) {
+        let Some(blend_target) = entity::get_component(layer_id, animation_blend_target()) else {
+            return;
+        };
 
-            (PlayerAnimationState::Punching, PlayerAnimationEvent::Walk) => {
-                PlayerAnimationState::Walking
-            }
+        let duration = entity::get_component(layer_id, animation_blend_duration()).unwrap();
+        let elapsed = entity::get_component(layer_id, animation_blend_elapsed()).unwrap() + dt;
+        if elapsed >= duration {
+            // blend finished, the faded in clip is all that is left to play
+            self.play_immediately(layer_id, AnimationNode(blend_target));
+            return;
+        }
 
-            (PlayerAnimationState::Punching, PlayerAnimationEvent::Dash) => {
-                PlayerAnimationState::Dashing
-            }
+        let blend_node = entity::get_component(layer_id, animation_root_node()).unwrap();
+        BlendNode(AnimationNode(blend_node)).set_weight(elapsed / duration);
+        entity::set_component(layer_id, animation_blend_elapsed(), elapsed);
+    }
 
-            (PlayerAnimationState::Punching, PlayerAnimationEvent::Punch) => {
-                PlayerAnimationState::Punching
-            }
+    fn update_fade(&mut self, layer_id: EntityId, layer: &AnimationLayer, dt: f32) {
+        let fade_rate =
+            entity::get_component(layer_id, animation_layer_fade_rate()).unwrap_or_default();
+        if fade_rate == 0.0 {
+            return;
+        }
 
-            (PlayerAnimationState::Punching, PlayerAnimationEvent::Jump) => {
-                PlayerAnimationState::Jumping
-            }
+        let weight = entity::get_component(layer_id, animation_layer_weight()).unwrap_or_default();
+        let weight = (weight + fade_rate * dt).clamp(0.0, 1.0);
+        if weight == 0.0 || weight == 1.0 {
+            entity::set_component(layer_id, animation_layer_fade_rate(), 0.0);
+        }
+        self.set_layer_weight(layer_id, layer, weight);
+    }
 
-            //Jumping
-            (PlayerAnimationState::Jumping, PlayerAnimationEvent::Stop) => {
-                PlayerAnimationState::Jumping
-            }
+    /// Feeds the horizontal speed of the mech in world units per second and its strafe direction,
+    /// from -1 (left) to 1 (right), into the blend spaces of the current states
+    pub fn set_locomotion(&mut self, speed: f32, strafe: f32) {
+        for (layer, layer_id) in self.layers() {
+            self.set_layer_locomotion(layer_id, layer, speed, strafe);
+        }
+    }
 
-            (PlayerAnimationState::Jumping, PlayerAnimationEvent::Walk) => {
-                clip_looping = true;
-                PlayerAnimationState::Walking
-            }
+    fn set_layer_locomotion(
+        &mut self,
+        layer_id: EntityId,
+        layer: &AnimationLayer,
+        speed: f32,
+        strafe: f32,
+    ) {
+        let Some(blend_nodes) = entity::get_component(layer_id, locomotion_blend_nodes()) else {
+            return;
+        };
+        let Some(clip_nodes) = entity::get_component(layer_id, locomotion_clip_nodes()) else {
+            return;
+        };
 
-            (PlayerAnimationState::Jumping, PlayerAnimationEvent::Dash) => {
-                PlayerAnimationState::Dashing
-            }
+        let Some(StateMotion::BlendSpace(blend_space)) = self
+            .current_state(layer_id, layer)
+            .ok()
+            .and_then(|state| layer.state(state))
+            .map(|state| &state.motion)
+        else {
+            return;
+        };
+
+        let weights = blend_space.weights(speed, strafe);
+        let (speed_blends, strafe_blends) = blend_nodes.split_at(weights.speed_weights.len());
+        for (&node, &weight) in speed_blends.iter().zip(&weights.speed_weights) {
+            BlendNode(AnimationNode(node)).set_weight(weight);
+        }
+        BlendNode(AnimationNode(strafe_blends[0])).set_weight(weights.strafe_side);
+        BlendNode(AnimationNode(strafe_blends[1])).set_weight(weights.strafe_weight);
+
+        let (sample_clips, strafe_clips) = clip_nodes.split_at(weights.sample_rates.len());
+        for (&clip, &rate) in sample_clips.iter().zip(&weights.sample_rates) {
+            entity::add_component(clip, playback_speed(), rate);
+        }
+        for &clip in strafe_clips {
+            entity::add_component(clip, playback_speed(), weights.strafe_rate);
+        }
+        entity::set_component(layer_id, animation_playback_rate(), weights.cycle_rate);
+    }
 
-            (PlayerAnimationState::Jumping, PlayerAnimationEvent::Punch) => {
-                PlayerAnimationState::Jumping
+    /// The layers of the state machine paired with the entities holding their state
+    fn layers(&self) -> impl Iterator<Item = (&'static AnimationLayer, EntityId)> {
+        let layer_ids = entity::get_component(self.0, animation_layers()).unwrap_or_default();
+        state_machine().layers().iter().zip(layer_ids)
+    }
+
+    /// Builds the animation node for a state, either its clip or its blend space tree
+    fn make_node(&self, layer_id: EntityId, animation_state: &AnimationState) -> AnimationNode {
+        match &animation_state.motion {
+            StateMotion::Clip(clip_path) => {
+                entity::remove_component(layer_id, locomotion_blend_nodes());
+                entity::remove_component(layer_id, locomotion_clip_nodes());
+                make_clip(clip_path, animation_state.looping).0
             }
-            (PlayerAnimationState::Jumping, PlayerAnimationEvent::Jump) => {
-                PlayerAnimationState::Jumping
+            StateMotion::BlendSpace(blend_space) => {
+                self.make_blend_space(layer_id, blend_space, animation_state.looping)
             }
-        };
+            StateMotion::Empty => unreachable!("states without motion have no animation node"),
+        }
+    }
 
-        if current_state.eq(&next_state) {
-            // return early no state transition
-            return next_state;
+    fn make_blend_space(
+        &self,
+        layer_id: EntityId,
+        blend_space: &BlendSpace,
+        looping: bool,
+    ) -> AnimationNode {
+        let sample_clips: Vec<PlayClipFromUrlNode> = blend_space
+            .samples
+            .iter()
+            .map(|sample| make_clip(&sample.clip, looping))
+            .collect();
+
+        // chain the speed samples, each blend node fades from everything slower into the next
+        let mut blend_nodes = vec![];
+        let mut speed_root = AnimationNode(sample_clips[0].0 .0);
+        for clip in &sample_clips[1..] {
+            let blend = BlendNode::new(speed_root, clip, 0.0);
+            blend_nodes.push(blend.0 .0);
+            speed_root = AnimationNode(blend.0 .0);
         }
 
-        println!(
-            "animation transition {:?} -> {:?} = {:?}",
-            current_state, event, next_state
+        let strafe_left = make_clip(&blend_space.strafe_left.clip, looping);
+        let strafe_right = make_clip(&blend_space.strafe_right.clip, looping);
+        let strafe_side = BlendNode::new(&strafe_left, &strafe_right, 0.0);
+        let strafe = BlendNode::new(speed_root, &strafe_side, 0.0);
+        blend_nodes.push(strafe_side.0 .0);
+        blend_nodes.push(strafe.0 .0);
+
+        let clip_nodes = sample_clips
+            .iter()
+            .chain([&strafe_left, &strafe_right])
+            .map(|clip| clip.0 .0)
+            .collect();
+        entity::add_components(
+            layer_id,
+            Entity::new()
+                .with(locomotion_blend_nodes(), blend_nodes)
+                .with(locomotion_clip_nodes(), clip_nodes),
         );
 
-        let clip_path = lookup_clip_path(next_state);
-        let clip = PlayClipFromUrlNode::new(asset::url(clip_path).unwrap());
-        clip.looping(clip_looping);
+        AnimationNode(strafe.0 .0)
+    }
 
-        let anim_player_id =
-            entity::get_component(target_entity_id, apply_animation_player()).unwrap();
+    /// Crossfades from the layer's current root node into `node`. Interrupting a running blend
+    /// nests it inside the new one, so the new blend starts from the current mixed pose.
+    fn start_blend(&self, layer_id: EntityId, node: AnimationNode, duration: f32) {
+        let mut depth =
+            entity::get_component(layer_id, animation_blend_depth()).map_or(0, |depth| depth + 1);
+        if depth > MAX_NESTED_BLENDS {
+            // too many transitions in a row to keep fading all of them, snap to the running one
+            let blend_target = entity::get_component(layer_id, animation_blend_target()).unwrap();
+            self.play_immediately(layer_id, AnimationNode(blend_target));
+            depth = 0;
+        }
 
-        let anim_player = AnimationPlayer(anim_player_id);
-        anim_player.play(clip);
+        let current_root = entity::get_component(layer_id, animation_root_node()).unwrap();
+        let blend = BlendNode::new(AnimationNode(current_root), &node, 0.0);
 
+        entity::add_components(
+            layer_id,
+            Entity::new()
+                .with(animation_root_node(), blend.0 .0)
+                .with(animation_blend_target(), node.0)
+                .with(animation_blend_elapsed(), 0.0)
+                .with(animation_blend_duration(), duration)
+                .with(animation_blend_depth(), depth),
+        );
+        self.compose();
+    }
+
+    /// Makes `node` all the layer plays, despawning whatever it played before. `node` may be
+    /// part of that, like the clip a finished blend faded in.
+    fn play_immediately(&self, layer_id: EntityId, node: AnimationNode) {
+        let previous_root = entity::get_component(layer_id, animation_root_node())
+            .filter(|&previous_root| previous_root != node.0);
+        detach_node(node.0);
+
+        entity::add_component(layer_id, animation_root_node(), node.0);
+        entity::remove_component(layer_id, animation_blend_target());
+        entity::remove_component(layer_id, animation_blend_elapsed());
+        entity::remove_component(layer_id, animation_blend_duration());
+        entity::remove_component(layer_id, animation_blend_depth());
+        self.compose();
+
+        if let Some(previous_root) = previous_root {
+            despawn_node(previous_root);
+        }
+    }
+
+    /// Fades the weight of a layer towards `target` over `duration` seconds
+    fn fade_layer(&self, layer_id: EntityId, layer: &AnimationLayer, target: f32, duration: f32) {
+        if duration <= 0.0 {
+            entity::set_component(layer_id, animation_layer_fade_rate(), 0.0);
+            self.set_layer_weight(layer_id, layer, target);
+            return;
+        }
+        let weight = entity::get_component(layer_id, animation_layer_weight()).unwrap_or_default();
         entity::set_component(
-            target_entity_id,
-            player_animation_state(),
-            next_state as u32,
+            layer_id,
+            animation_layer_fade_rate(),
+            (target - weight) / duration,
         );
-        next_state
+    }
+
+    fn set_layer_weight(&self, layer_id: EntityId, layer: &AnimationLayer, weight: f32) {
+        entity::set_component(layer_id, animation_layer_weight(), weight);
+        if let Some(blend_node) = entity::get_component(layer_id, animation_layer_blend_node()) {
+            set_mask_weight(&BlendNode(AnimationNode(blend_node)), &layer.mask, weight);
+        }
+    }
+
+    /// Rebuilds the tree the animation player plays from the root nodes of the layers, this runs
+    /// whenever one of them changes. Every masked layer keeps its blend node, which only gets its
+    /// inputs rewired.
+    fn compose(&self) {
+        let mut layers = self.layers();
+        let Some((_, base_layer_id)) = layers.next() else { return; };
+        let Some(mut root) = entity::get_component(base_layer_id, animation_root_node()) else {
+            return;
+        };
+
+        for (layer, layer_id) in layers {
+            let Some(layer_root) = entity::get_component(layer_id, animation_root_node()) else {
+                continue;
+            };
+            root = match entity::get_component(layer_id, animation_layer_blend_node()) {
+                Some(blend) => {
+                    set_blend_inputs(blend, [root, layer_root]);
+                    blend
+                }
+                None => {
+                    // the blend weight keeps everything outside of the mask on the layers below
+                    let blend = BlendNode::new(AnimationNode(root), AnimationNode(layer_root), 0.0);
+                    let weight = entity::get_component(layer_id, animation_layer_weight())
+                        .unwrap_or_default();
+                    set_mask_weight(&blend, &layer.mask, weight);
+                    entity::add_component(layer_id, animation_layer_blend_node(), blend.0 .0);
+                    blend.0 .0
+                }
+            };
+        }
+
+        match entity::get_component(self.0, apply_animation_player()) {
+            Some(anim_player) => AnimationPlayer(anim_player).play(&AnimationNode(root)),
+            None => {
+                let anim_player = AnimationPlayer::new(&AnimationNode(root));
+                entity::add_component(self.0, apply_animation_player(), anim_player.0);
+            }
+        }
+
+        if cfg!(debug_assertions) {
+            let size = tree_size(root);
+            debug_assert!(
+                size <= max_tree_size(),
+                "animation tree of {} grew to {size} nodes",
+                self.0
+            );
+        }
     }
 }
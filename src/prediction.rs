@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+
+use crate::{
+    components::{
+        authoritative_rotation, authoritative_translation, authoritative_vertical_rotation_angle,
+        is_grounded, is_knocked_out, player_input_ack, player_jump_height,
+        player_vertical_rotation_angle, player_vertical_velocity, punch_elapsed,
+        view_vertical_rotation,
+    },
+    input_sequence::is_newer,
+    movement::{MovementInput, MovementState, DEFAULT_JUMP_HEIGHT},
+};
+use ambient_api::{
+    components::core::{
+        player::{local_user_id, player, user_id},
+        transform::{rotation, translation},
+    },
+    prelude::*,
+};
+
+// inputs kept for replaying, about two seconds of round trip at 60 frames per second. Older
+// unacknowledged inputs got lost and are dropped.
+const MAX_PENDING_INPUTS: usize = 120;
+
+/// The input of a frame, not acknowledged by the server yet
+struct PendingInput {
+    /// sequence of the input command the frame was sent in
+    sequence: u32,
+    input: MovementInput,
+    dt: f32,
+}
+
+/// Predicted state of the local player
+struct PredictedState {
+    position: Vec3,
+    movement: MovementState,
+    /// height of the ground the server last saw the player standing on
+    floor: Option<f32>,
+}
+
+impl PredictedState {
+    /// The client has no physics, the player lands on the height it last stood at and collides
+    /// with nothing else
+    fn step(&mut self, input: &MovementInput, can_dash: bool, jump_height: f32, dt: f32) {
+        self.movement.turn(input.look);
+        let velocity = self.movement.velocity(input, can_dash, jump_height, dt);
+        self.position += velocity * dt;
+
+        let landed = match self.floor {
+            Some(floor) if self.position.z <= floor => {
+                self.position.z = floor;
+                true
+            }
+            _ => false,
+        };
+        self.movement.collide(landed, false);
+    }
+}
+
+/// Client side prediction of the local player. The input of every frame moves the local player
+/// right away with the movement steps the server runs, instead of waiting a round trip for the
+/// server to do it.
+///
+/// The inputs stay buffered with the sequence of the input command they were sent in until the
+/// server acknowledges it in `player_input_ack`, which it replicates together with the state it
+/// simulated the command into. With every new acknowledgement the prediction starts over from
+/// that authoritative state and replays the inputs the server hasn't simulated yet. Knockback
+/// and collisions the client can't predict get corrected that way within a round trip.
+///
+/// The prediction overwrites the transform of the local player every frame, and the server only
+/// replicates what changed on its side, so the transform can't be told apart from the
+/// prediction. The authoritative one comes from the `authoritative_*` components instead, which
+/// the server mirrors it into and the client never writes.
+///
+/// While the input sender rests there is nothing to predict and the local player follows the
+/// replicated state.
+#[derive(Default)]
+pub struct LocalPrediction {
+    player_id: Option<EntityId>,
+    last_ack: Option<u32>,
+    pending: VecDeque<PendingInput>,
+    predicted: Option<PredictedState>,
+}
+
+impl LocalPrediction {
+    /// Moves the local player by the `input` of a frame lasting `dt` seconds. `sequence` is the
+    /// input command the input is sent in, `None` if it isn't sent at all.
+    pub fn predict(&mut self, input: MovementInput, dt: f32, sequence: Option<u32>) {
+        let Some(player_id) = self.local_player() else { return; };
+        // the server ignores the input of knocked out players and respawns them elsewhere, start
+        // over from whatever it replicates next
+        let Some(sequence) = sequence
+            .filter(|_| !entity::get_component(player_id, is_knocked_out()).unwrap_or_default())
+        else {
+            self.pending.clear();
+            self.last_ack = None;
+            // the server doesn't replicate a transform that stays the same, put back the last
+            // one it did instead of leaving the player where the prediction ended
+            if self.predicted.take().is_some() {
+                if let Some((position, movement)) = authoritative_state(player_id) {
+                    show(player_id, position, &movement);
+                }
+            }
+            return;
+        };
+
+        self.reconcile(player_id);
+        let can_dash = !entity::has_component(player_id, punch_elapsed());
+        let jump_height =
+            entity::get_component(player_id, player_jump_height()).unwrap_or(DEFAULT_JUMP_HEIGHT);
+        let Some(predicted) = self.predicted.as_mut() else { return; };
+        predicted.step(&input, can_dash, jump_height, dt);
+
+        self.pending.push_back(PendingInput {
+            sequence,
+            input,
+            dt,
+        });
+        if self.pending.len() > MAX_PENDING_INPUTS {
+            self.pending.pop_front();
+        }
+
+        // the replicated state overwrites this whenever it changes on the server, the next frame
+        // predicts over it again
+        show(player_id, predicted.position, &predicted.movement);
+    }
+
+    /// Starts over from the authoritative state if the server acknowledged new inputs, or if
+    /// the prediction just started
+    fn reconcile(&mut self, player_id: EntityId) {
+        let Some(ack) = entity::get_component(player_id, player_input_ack()) else { return; };
+        if self.last_ack == Some(ack) && self.predicted.is_some() {
+            return;
+        }
+        self.last_ack = Some(ack);
+        self.pending
+            .retain(|pending| is_newer(pending.sequence, ack));
+
+        let Some((position, movement)) = authoritative_state(player_id) else { return; };
+        let previous_floor = self
+            .predicted
+            .as_ref()
+            .and_then(|predicted| predicted.floor);
+        let mut predicted = PredictedState {
+            position,
+            movement,
+            floor: if movement.is_grounded {
+                Some(position.z)
+            } else {
+                previous_floor
+            },
+        };
+
+        // punches and the jump height only change on the server, replaying with their current
+        // values is close enough
+        let can_dash = !entity::has_component(player_id, punch_elapsed());
+        let jump_height =
+            entity::get_component(player_id, player_jump_height()).unwrap_or(DEFAULT_JUMP_HEIGHT);
+        for pending in &self.pending {
+            predicted.step(&pending.input, can_dash, jump_height, pending.dt);
+        }
+        self.predicted = Some(predicted);
+    }
+
+    fn local_player(&mut self) -> Option<EntityId> {
+        if self.player_id.is_none() {
+            let local_user = entity::get_component(entity::resources(), local_user_id())?;
+            self.player_id = entity::get_all(player())
+                .into_iter()
+                .find(|&id| entity::get_component(id, user_id()).as_ref() == Some(&local_user));
+        }
+        self.player_id
+    }
+}
+
+/// Where the server last simulated the local player to
+fn authoritative_state(player_id: EntityId) -> Option<(Vec3, MovementState)> {
+    let movement = MovementState {
+        rotation: entity::get_component(player_id, authoritative_rotation())?,
+        vertical_rotation_angle: entity::get_component(
+            player_id,
+            authoritative_vertical_rotation_angle(),
+        )?,
+        vertical_velocity: entity::get_component(player_id, player_vertical_velocity())
+            .unwrap_or_default(),
+        is_grounded: entity::get_component(player_id, is_grounded()).unwrap_or_default(),
+    };
+    Some((
+        entity::get_component(player_id, authoritative_translation())?,
+        movement,
+    ))
+}
+
+/// Shows the local player at `position`, turned like `movement`
+fn show(player_id: EntityId, position: Vec3, movement: &MovementState) {
+    entity::set_component(player_id, translation(), position);
+    entity::set_component(player_id, rotation(), movement.rotation);
+    entity::set_component(
+        player_id,
+        player_vertical_rotation_angle(),
+        movement.vertical_rotation_angle,
+    );
+    entity::set_component(
+        player_id,
+        view_vertical_rotation(),
+        movement.view_vertical_rotation(),
+    );
+}
@@ -0,0 +1,395 @@
+use crate::{
+    components::{
+        knockback_velocity, punch_cooldown, punch_elapsed, punch_hit_targets, punch_hitbox_active,
+    },
+    health::Health,
+    lag_compensation::TransformHistory,
+};
+use ambient_api::{components::core::transform::translation, prelude::*};
+
+// timings in seconds, windup + active + recovery roughly match the length of punch_4.anim. The
+// hit window itself is opened and closed by the hit_active and hit_inactive notifies of the punch
+// animation, so hits line up with the fist instead of the input.
+const PUNCH_WINDUP: f32 = 0.15;
+const PUNCH_ACTIVE: f32 = 0.1;
+const PUNCH_RECOVERY: f32 = 0.17;
+const PUNCH_DURATION: f32 = PUNCH_WINDUP + PUNCH_ACTIVE + PUNCH_RECOVERY;
+const PUNCH_COOLDOWN: f32 = 0.3;
+
+// hit volume is a sphere in front of the attacker
+const PUNCH_REACH: f32 = 3.0; // world units along the attackers forward vector
+const PUNCH_RADIUS: f32 = 2.5; // world units
+
+const PUNCH_DAMAGE: f32 = 10.0;
+
+const KNOCKBACK_SPEED: f32 = 25.0; // world units per second
+const KNOCKBACK_LIFT: f32 = 0.2; // fraction of the knockback pointing upwards
+const KNOCKBACK_DECAY: f32 = 6.0; // per second
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PunchPhase {
+    Windup,
+    Active,
+    Recovery,
+}
+
+impl PunchPhase {
+    fn from_elapsed(elapsed: f32, hitbox_active: bool) -> Option<Self> {
+        if elapsed >= PUNCH_DURATION {
+            None
+        } else if hitbox_active {
+            Some(PunchPhase::Active)
+        } else if elapsed < PUNCH_WINDUP {
+            Some(PunchPhase::Windup)
+        } else {
+            // the hit window closed, or the animation got interrupted before it opened
+            Some(PunchPhase::Recovery)
+        }
+    }
+}
+
+/// Server side punch action of a player. The punch is in progress while the player has a
+/// `punch_elapsed` component, after it finishes `punch_cooldown` has to run out before the next
+/// punch can start. Knocked out players don't punch.
+pub struct Punch(pub EntityId);
+impl Punch {
+    /// Advances the punch by `dt`, starting a new one if `requested` and the player is able to.
+    /// Returns the phase the punch is in after the update, or `None` if the player isn't punching.
+    pub fn update(&self, requested: bool, dt: f32) -> Option<PunchPhase> {
+        let knocked_out = Health(self.0).is_knocked_out();
+        let mut state = PunchState::load(self.0);
+        let phase = state.update(requested, knocked_out, dt);
+        state.store(self.0);
+        phase
+    }
+
+    /// Drops the punch in progress, if any, without starting the cooldown
+    pub fn cancel(&self) {
+        let mut state = PunchState::load(self.0);
+        state.cancel();
+        state.store(self.0);
+    }
+
+    pub fn is_punching(&self) -> bool {
+        entity::has_component(self.0, punch_elapsed())
+    }
+
+    /// Opens or closes the hit window of the punch in progress, driven by the punch animation's
+    /// notifies. Does nothing while the player isn't punching.
+    pub fn set_hitbox_active(&self, active: bool) {
+        if self.is_punching() {
+            entity::set_component(self.0, punch_hitbox_active(), active);
+        }
+    }
+
+    /// Checks the hit volume in front of the attacker against `targets`. Every target is hit at
+    /// most once per punch, gets knocked back and damaged and a `PunchHit` message is sent for it.
+    ///
+    /// The attacker punches from where it is now, but the targets are checked where `history`
+    /// had them at `time`, the moment the attacker saw them at when punching.
+    pub fn resolve_hits(
+        &self,
+        forward: Vec3,
+        targets: &[EntityId],
+        history: &TransformHistory,
+        time: f32,
+    ) {
+        let attacker_id = self.0;
+        let Some(attacker_position) = entity::get_component(attacker_id, translation()) else {
+            return;
+        };
+        let targets: Vec<PunchTarget> = targets
+            .iter()
+            .filter_map(|&id| {
+                let position = history
+                    .transform_at(id, time)
+                    .map(|(position, _)| position)
+                    .or_else(|| entity::get_component(id, translation()))?;
+                Some(PunchTarget {
+                    id,
+                    position,
+                    knocked_out: Health(id).is_knocked_out(),
+                })
+            })
+            .collect();
+        let mut hit_targets =
+            entity::get_component(attacker_id, punch_hit_targets()).unwrap_or_default();
+
+        let struck = struck_targets(
+            attacker_id,
+            attacker_position,
+            forward,
+            &targets,
+            &hit_targets,
+        );
+        for target_id in struck {
+            let knockback = (forward + Vec3::Z * KNOCKBACK_LIFT).normalize() * KNOCKBACK_SPEED;
+            entity::set_component(target_id, knockback_velocity(), knockback);
+            hit_targets.push(target_id);
+
+            let hit = messages::PunchHit::new(attacker_id, target_id, knockback);
+            hit.send_local_broadcast(false);
+            hit.send_client_broadcast_reliable();
+            messages::Damage::new(target_id, attacker_id, PUNCH_DAMAGE).send_local_broadcast(true);
+        }
+
+        entity::set_component(attacker_id, punch_hit_targets(), hit_targets);
+    }
+}
+
+/// What a punch keeps track of between frames, stored in the attacker's components. The punch
+/// logic only works on this, so it doesn't need the ECS.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PunchState {
+    /// seconds since the punch started, `None` while the player isn't punching
+    elapsed: Option<f32>,
+    hitbox_active: bool,
+    /// players already hit by the punch
+    hit_targets: Vec<EntityId>,
+    cooldown: f32,
+}
+
+impl PunchState {
+    fn load(player_id: EntityId) -> Self {
+        Self {
+            elapsed: entity::get_component(player_id, punch_elapsed()),
+            hitbox_active: entity::get_component(player_id, punch_hitbox_active())
+                .unwrap_or_default(),
+            hit_targets: entity::get_component(player_id, punch_hit_targets()).unwrap_or_default(),
+            cooldown: entity::get_component(player_id, punch_cooldown()).unwrap_or_default(),
+        }
+    }
+
+    fn store(&self, player_id: EntityId) {
+        entity::set_component(player_id, punch_cooldown(), self.cooldown);
+        let Some(elapsed) = self.elapsed else {
+            entity::remove_component(player_id, punch_elapsed());
+            entity::remove_component(player_id, punch_hitbox_active());
+            entity::remove_component(player_id, punch_hit_targets());
+            return;
+        };
+        entity::add_components(
+            player_id,
+            Entity::new()
+                .with(punch_elapsed(), elapsed)
+                .with(punch_hitbox_active(), self.hitbox_active)
+                .with(punch_hit_targets(), self.hit_targets.clone()),
+        );
+    }
+
+    fn update(&mut self, requested: bool, knocked_out: bool, dt: f32) -> Option<PunchPhase> {
+        let cooldown = self.cooldown;
+        self.cooldown = (cooldown - dt).max(0.0);
+        if knocked_out {
+            self.cancel();
+            return None;
+        }
+
+        let Some(elapsed) = self.elapsed else {
+            if requested && cooldown <= 0.0 {
+                *self = Self {
+                    elapsed: Some(0.0),
+                    ..Self::default()
+                };
+                return Some(PunchPhase::Windup);
+            }
+            return None;
+        };
+
+        let elapsed = elapsed + dt;
+        let phase = PunchPhase::from_elapsed(elapsed, self.hitbox_active);
+        if phase.is_some() {
+            self.elapsed = Some(elapsed);
+        } else {
+            self.cancel();
+            self.cooldown = PUNCH_COOLDOWN;
+        }
+        phase
+    }
+
+    fn cancel(&mut self) {
+        self.elapsed = None;
+        self.hitbox_active = false;
+        self.hit_targets.clear();
+    }
+}
+
+/// A player a punch may hit, where the attacker saw them
+#[derive(Debug, Clone, Copy)]
+struct PunchTarget {
+    id: EntityId,
+    position: Vec3,
+    knocked_out: bool,
+}
+
+/// The targets a punch of `attacker_id` hits now, leaving out the ones it `already_hit`.
+/// Knocked out players can't be hit.
+fn struck_targets(
+    attacker_id: EntityId,
+    attacker_position: Vec3,
+    forward: Vec3,
+    targets: &[PunchTarget],
+    already_hit: &[EntityId],
+) -> Vec<EntityId> {
+    targets
+        .iter()
+        .filter(|target| target.id != attacker_id && !already_hit.contains(&target.id))
+        .filter(|target| !target.knocked_out)
+        .filter(|target| in_hit_volume(attacker_position, forward, target.position))
+        .map(|target| target.id)
+        .collect()
+}
+
+/// Whether a target at `target_position` is inside the hit volume of an attacker at
+/// `attacker_position` punching towards `forward`
+fn in_hit_volume(attacker_position: Vec3, forward: Vec3, target_position: Vec3) -> bool {
+    target_position.distance(attacker_position + forward * PUNCH_REACH) <= PUNCH_RADIUS
+}
+
+/// Knockback velocity after it decayed for `dt` seconds
+pub fn decay_knockback(knockback: Vec3, dt: f32) -> Vec3 {
+    knockback * (-KNOCKBACK_DECAY * dt).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn punch_phases_follow_the_elapsed_time_and_hit_window() {
+        let cases = [
+            (0.0, false, Some(PunchPhase::Windup)),
+            (PUNCH_WINDUP - 0.001, false, Some(PunchPhase::Windup)),
+            // the animation opens the hit window, whenever that is
+            (0.0, true, Some(PunchPhase::Active)),
+            (PUNCH_WINDUP, true, Some(PunchPhase::Active)),
+            (PUNCH_DURATION - 0.001, true, Some(PunchPhase::Active)),
+            // past the windup without a hit window, it closed or never opened
+            (PUNCH_WINDUP, false, Some(PunchPhase::Recovery)),
+            (
+                PUNCH_WINDUP + PUNCH_ACTIVE,
+                false,
+                Some(PunchPhase::Recovery),
+            ),
+            (PUNCH_DURATION - 0.001, false, Some(PunchPhase::Recovery)),
+            // over, even if the hit window never closed
+            (PUNCH_DURATION, false, None),
+            (PUNCH_DURATION, true, None),
+            (PUNCH_DURATION + 1.0, false, None),
+        ];
+        for (elapsed, hitbox_active, expected) in cases {
+            assert_eq!(
+                PunchPhase::from_elapsed(elapsed, hitbox_active),
+                expected,
+                "{elapsed} {hitbox_active}"
+            );
+        }
+    }
+
+    #[test]
+    fn punches_hit_up_to_the_edge_of_the_volume() {
+        let attacker = vec3(10.0, -4.0, 2.0);
+        let forward = Vec3::X;
+        let center = attacker + forward * PUNCH_REACH;
+        let edges = [
+            // farthest, nearest and to the sides, above and below the center
+            center + Vec3::X * PUNCH_RADIUS,
+            center - Vec3::X * PUNCH_RADIUS,
+            center + Vec3::Y * PUNCH_RADIUS,
+            center - Vec3::Y * PUNCH_RADIUS,
+            center + Vec3::Z * PUNCH_RADIUS,
+            center - Vec3::Z * PUNCH_RADIUS,
+        ];
+        for edge in edges {
+            assert!(in_hit_volume(attacker, forward, edge), "{edge}");
+            let beyond = center + (edge - center) * 1.01;
+            assert!(!in_hit_volume(attacker, forward, beyond), "{beyond}");
+        }
+        assert!(in_hit_volume(attacker, forward, center));
+    }
+
+    #[test]
+    fn knocked_out_players_stop_punching() {
+        let mut state = PunchState {
+            elapsed: Some(PUNCH_WINDUP),
+            hitbox_active: true,
+            hit_targets: vec![EntityId::null()],
+            cooldown: 0.0,
+        };
+        assert_eq!(state.update(false, false, 0.01), Some(PunchPhase::Active));
+
+        // the active punch stops right away instead of landing its hit
+        assert_eq!(state.update(false, true, 0.01), None);
+        assert_eq!(state, PunchState::default());
+        // and no new one starts while knocked out
+        assert_eq!(state.update(true, true, 0.01), None);
+        assert_eq!(state.elapsed, None);
+        assert_eq!(state.update(true, false, 0.01), Some(PunchPhase::Windup));
+    }
+
+    #[test]
+    fn knocking_out_cancels_the_punch_without_a_cooldown() {
+        let mut state = PunchState {
+            elapsed: Some(PUNCH_WINDUP),
+            hitbox_active: true,
+            hit_targets: vec![EntityId::null()],
+            cooldown: 0.0,
+        };
+        state.cancel();
+        assert_eq!(state, PunchState::default());
+    }
+
+    #[test]
+    fn punches_run_their_course_then_cool_down() {
+        let mut state = PunchState::default();
+        assert_eq!(state.update(true, false, 0.01), Some(PunchPhase::Windup));
+        state.hitbox_active = true;
+        assert_eq!(
+            state.update(false, false, PUNCH_WINDUP),
+            Some(PunchPhase::Active)
+        );
+        assert_eq!(state.update(false, false, PUNCH_DURATION), None);
+        assert_eq!(state.elapsed, None);
+        assert_eq!(state.cooldown, PUNCH_COOLDOWN);
+        // requesting during the cooldown does nothing
+        assert_eq!(state.update(true, false, PUNCH_COOLDOWN / 2.0), None);
+        assert_eq!(state.update(true, false, PUNCH_COOLDOWN), None);
+        assert_eq!(state.update(true, false, 0.01), Some(PunchPhase::Windup));
+    }
+
+    #[test]
+    fn knocked_out_targets_are_not_hit() {
+        let (attacker, standing, knocked_out, already_hit) = (
+            EntityId { id0: 1, id1: 0 },
+            EntityId { id0: 2, id1: 0 },
+            EntityId { id0: 3, id1: 0 },
+            EntityId { id0: 4, id1: 0 },
+        );
+        let in_reach = Vec3::X * PUNCH_REACH;
+        let targets = [attacker, standing, knocked_out, already_hit].map(|id| PunchTarget {
+            id,
+            position: if id == attacker { Vec3::ZERO } else { in_reach },
+            knocked_out: id == knocked_out,
+        });
+        assert_eq!(
+            struck_targets(attacker, Vec3::ZERO, Vec3::X, &targets, &[already_hit]),
+            [standing]
+        );
+    }
+
+    #[test]
+    fn punches_only_hit_in_front() {
+        let forward = Quat::from_rotation_z(1.0) * Vec3::X;
+        let targets = [
+            // behind, and the spot of the attacker itself, as far from the center as the reach
+            -forward * PUNCH_REACH,
+            Vec3::ZERO,
+            // beside, at the height of the center
+            forward.cross(Vec3::Z) * PUNCH_REACH,
+        ];
+        for target in targets {
+            assert!(!in_hit_volume(Vec3::ZERO, forward, target), "{target}");
+        }
+        assert!(in_hit_volume(Vec3::ZERO, forward, forward * PUNCH_REACH));
+    }
+}
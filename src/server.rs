@@ -1,10 +1,13 @@
-use core::f32::consts::FRAC_PI_2;
+use std::{cell::RefCell, rc::Rc};
 
 use ambient_api::{
     components::core::{
         app::main_scene,
         ecs::{children, parent},
-        physics::{character_controller_height, character_controller_radius, plane_collider},
+        physics::{
+            character_controller_height, character_controller_radius, physics_controlled,
+            plane_collider,
+        },
         player::{player, user_id},
         prefab::prefab_from_url,
         primitives::{cube, quad},
@@ -20,14 +23,65 @@ use ambient_api::{
 };
 
 use crate::components::{
-    is_dashing, is_jumping, is_punching, player_input_direction, player_mesh_ref,
-    player_mouse_delta_x, player_mouse_delta_y, player_text_container_ref,
-    player_vertical_rotation_angle, view_vertical_rotation,
+    animation_state_machine_config, authoritative_rotation, authoritative_translation,
+    authoritative_vertical_rotation_angle, dash_cooldown, health, is_dashing, is_grounded,
+    is_jumping, is_knocked_out, is_punching, knockback_velocity, knockout_timer, max_health,
+    network_simulation_enabled, player_dash_requested, player_input_ack, player_input_bindings,
+    player_input_budget, player_input_direction, player_input_flagged, player_input_last_violation,
+    player_input_sequence, player_input_suspicion, player_input_violations, player_inputs_dropped,
+    player_inputs_stale, player_jump_height, player_look, player_mesh_ref, player_snapshot_time,
+    player_text_container_ref, player_text_ref, player_vertical_rotation_angle,
+    player_vertical_velocity, punch_cooldown, punch_rewind, simulated_client_conditions,
+    view_vertical_rotation,
 };
 
+mod animation_state_machine;
+use animation_state_machine::{
+    install_state_machine, state_machine, STATE_MACHINE_ASSET, STATE_MACHINE_CONFIG,
+};
+mod blend_space;
 mod player_animation_controller;
 use player_animation_controller::{PlayerAnimationController, PlayerAnimationEvent};
 
+mod lag_compensation;
+use lag_compensation::{rewind_for, TransformHistory};
+
+mod punch;
+use punch::{decay_knockback, Punch, PunchPhase};
+
+mod health;
+use health::{Health, DEFAULT_MAX_HEALTH};
+
+mod spawn;
+use spawn::{make_spawn_points, respawn};
+
+mod input_sequence;
+use input_sequence::InputSequence;
+
+mod network_simulator;
+use network_simulator::{simulate, NetworkConditions};
+
+mod input_validation;
+use input_validation::{InputValidator, InputViolation, INPUT_MESSAGE_BURST, MAX_INPUT_COMMANDS};
+
+mod input_recording;
+use input_recording::{InputRecorder, InputReplay, ReceivedInput, ReplayDivergence, ReplayStep};
+
+mod debug_config;
+use debug_config::{DebugConfig, DebugConfigError, DEBUG_CONFIG_ASSET};
+
+mod bot;
+use bot::Bots;
+
+mod movement;
+use movement::{MovementInput, MovementState, DEFAULT_JUMP_HEIGHT};
+
+const KILL_HEIGHT: f32 = -50.0; // players falling below this get knocked out
+
+// players can have their own input bindings in this asset directory, in a file named after their
+// user id, see `assets/input_bindings.toml`
+const USER_BINDINGS_DIR: &str = "assets/input_bindings";
+
 fn make_text() -> Entity {
     Entity::new()
         .with(
@@ -42,10 +96,65 @@ fn make_text() -> Entity {
         .with_default(mesh_to_world())
 }
 
+/// Downloads a text file from the assets of the project
+async fn load_text_asset(path: &str) -> Result<String, String> {
+    let url = asset::url(path).ok_or_else(|| "not part of the built assets".to_string())?;
+    let bytes = http::get(url).await.map_err(|err| err.to_string())?;
+    String::from_utf8(bytes).map_err(|err| err.to_string())
+}
+
+/// Loads the animation state machine config from the assets and shares it with the clients. If
+/// the asset can't be loaded or is invalid, the errors get logged and the config bundled at build
+/// time is used instead.
+async fn load_animation_state_machine() {
+    let source = match load_text_asset(STATE_MACHINE_ASSET).await {
+        Ok(source) => match install_state_machine(&source) {
+            Ok(()) => Some(source),
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("animation state machine {STATE_MACHINE_ASSET}: {error}");
+                }
+                None
+            }
+        },
+        Err(error) => {
+            eprintln!("animation state machine {STATE_MACHINE_ASSET}: {error}");
+            None
+        }
+    };
+    let source = source.unwrap_or_else(|| {
+        eprintln!("animation state machine: using the config bundled at build time");
+        STATE_MACHINE_CONFIG.to_string()
+    });
+
+    // validates the bundled config if it is the one in use, so even that fails at startup
+    // rather than when the first player joins
+    state_machine();
+    entity::add_component(
+        entity::synchronized_resources(),
+        animation_state_machine_config(),
+        source,
+    );
+}
+
+/// Loads the debug settings from the assets. A missing or invalid file gets logged and leaves
+/// every debug feature off.
+async fn load_debug_config() -> DebugConfig {
+    let config = match load_text_asset(DEBUG_CONFIG_ASSET).await {
+        Ok(source) => DebugConfig::parse(&source),
+        Err(error) => Err(DebugConfigError::Load(error)),
+    };
+    config.unwrap_or_else(|error| {
+        eprintln!("debug config {DEBUG_CONFIG_ASSET}: {error}");
+        DebugConfig::default()
+    })
+}
+
 #[main]
-pub fn main() {
-    let world_front: Vec3 = Vec3::X;
-    let world_right: Vec3 = Vec3::Y;
+pub async fn main() {
+    // the state machine has to be in place before the first player joins
+    load_animation_state_machine().await;
+    let debug_config = load_debug_config().await;
 
     // ground entity
     Entity::new()
@@ -56,6 +165,24 @@ pub fn main() {
         .with_default(plane_collider())
         .spawn();
 
+    make_spawn_points();
+    let spawn_policy = debug_config.spawn.policy;
+
+    // the clients can't read files of their own, the server hands them their bindings
+    spawn_query((player(), user_id())).bind(|players| {
+        for (id, (_, user)) in players {
+            run_async(async move {
+                // most players don't have a bindings file and play with the defaults, which
+                // looks no different from failing to load one
+                let Ok(source) = load_text_asset(&format!("{USER_BINDINGS_DIR}/{user}.toml")).await
+                else {
+                    return;
+                };
+                entity::add_component(id, player_input_bindings(), source);
+            });
+        }
+    });
+
     spawn_query(player()).bind(move |players| {
         for (id, _) in players {
             // add mecha to player id
@@ -78,6 +205,7 @@ pub fn main() {
                 .with(text(), "player".to_string())
                 .with(parent(), id)
                 .spawn();
+            entity::add_component(id, player_text_ref(), text);
 
             let text_container = make_transformable()
                 .with_default(main_scene())
@@ -102,118 +230,383 @@ pub fn main() {
                     .with(color(), vec4(0.5, 0.0, 1.0, 1.0))
                     .with(character_controller_height(), 2.)
                     .with(character_controller_radius(), 0.5)
+                    .with_default(physics_controlled())
+                    .with_default(is_grounded())
+                    .with_default(player_vertical_velocity())
+                    .with(player_jump_height(), DEFAULT_JUMP_HEIGHT)
                     .with_default(player_input_direction())
                     .with_default(is_dashing())
                     .with_default(is_jumping())
                     .with_default(is_punching())
-                    .with_default(player_mouse_delta_x())
-                    .with_default(player_mouse_delta_y()),
+                    .with_default(knockback_velocity())
+                    .with_default(punch_cooldown())
+                    .with_default(punch_rewind())
+                    .with(health(), DEFAULT_MAX_HEALTH)
+                    .with(max_health(), DEFAULT_MAX_HEALTH)
+                    .with_default(is_knocked_out())
+                    .with_default(knockout_timer())
+                    .with_default(player_look())
+                    .with_default(player_input_sequence())
+                    .with_default(player_inputs_dropped())
+                    .with_default(player_inputs_stale())
+                    .with_default(player_input_ack())
+                    .with_default(authoritative_translation())
+                    .with_default(authoritative_rotation())
+                    .with_default(authoritative_vertical_rotation_angle())
+                    .with_default(player_snapshot_time())
+                    .with(player_input_budget(), INPUT_MESSAGE_BURST)
+                    .with_default(player_dash_requested())
+                    .with_default(dash_cooldown())
+                    .with_default(player_input_violations())
+                    .with_default(player_input_last_violation())
+                    .with_default(player_input_suspicion())
+                    .with_default(player_input_flagged()),
+            );
+            respawn(id, spawn_policy);
+        }
+    });
+
+    // capture input messages from client, they get applied at the start of the next tick. In
+    // debug builds they can go through a simulated network first, with the conditions of the
+    // debug config while `network_simulation_enabled`.
+    let inbox: Rc<RefCell<Vec<(EntityId, ReceivedInput)>>> = Rc::default();
+    let simulated_link = Rc::new(RefCell::new(None));
+    let simulator_config = debug_config.network_simulator;
+    match toml::to_string(&simulator_config.client) {
+        Ok(conditions) => entity::add_component(
+            entity::synchronized_resources(),
+            simulated_client_conditions(),
+            conditions,
+        ),
+        Err(error) => eprintln!("network simulator: {error}"),
+    }
+    entity::add_component(
+        entity::synchronized_resources(),
+        network_simulation_enabled(),
+        true,
+    );
+    // anyone can switch it for everyone, it's a debug build after all
+    messages::NetworkSimulation::subscribe(|_, msg| {
+        if cfg!(debug_assertions) {
+            entity::set_component(
+                entity::synchronized_resources(),
+                network_simulation_enabled(),
+                msg.enabled,
+            );
+        }
+    });
+    messages::Input::subscribe({
+        let (inbox, simulated_link) = (inbox.clone(), simulated_link.clone());
+        move |source, msg| {
+            let Some(player_id) = source.client_entity_id() else { return; };
+            let input = (player_id, ReceivedInput::Input(msg));
+            match simulated_link.borrow_mut().as_mut() {
+                Some(link) => link.send_unreliable(input),
+                None => inbox.borrow_mut().push(input),
+            }
+        }
+    });
+    messages::InputAction::subscribe({
+        let (inbox, simulated_link) = (inbox.clone(), simulated_link.clone());
+        move |source, msg| {
+            let Some(player_id) = source.client_entity_id() else { return; };
+            let input = (player_id, ReceivedInput::Action(msg));
+            match simulated_link.borrow_mut().as_mut() {
+                Some(link) => link.send_reliable(input),
+                None => inbox.borrow_mut().push(input),
+            }
+        }
+    });
+    ambient_api::messages::Frame::subscribe({
+        let inbox = inbox.clone();
+        move |_| {
+            let enabled = entity::get_component(
+                entity::synchronized_resources(),
+                network_simulation_enabled(),
+            )
+            .unwrap_or_default();
+            let mut simulated_link = simulated_link.borrow_mut();
+            simulate(
+                &mut simulated_link,
+                if enabled {
+                    simulator_config.server
+                } else {
+                    NetworkConditions::PERFECT
+                },
             );
+            let Some(link) = simulated_link.as_mut() else { return; };
+            inbox.borrow_mut().extend(link.receive(frametime()));
         }
     });
 
-    // capture input messages from client and update state
-    messages::Input::subscribe(move |source, msg| {
-        let Some(player_id) = source.client_entity_id() else { return; };
-        entity::set_component(player_id, is_dashing(), msg.is_dashing);
-        entity::set_component(player_id, is_jumping(), msg.is_jumping);
-        entity::set_component(player_id, is_punching(), msg.is_punching);
-        entity::set_component(player_id, player_input_direction(), msg.input_direction);
-        entity::set_component(player_id, player_mouse_delta_x(), msg.mouse_delta_x);
-        entity::set_component(player_id, player_mouse_delta_y(), msg.mouse_delta_y);
+    // damage is only ever dealt by the server itself, never by clients
+    messages::Damage::subscribe(move |source, msg| {
+        if source.client_user_id().is_some() {
+            return;
+        }
+        Health(msg.target).apply_damage(msg.amount);
     });
 
+    // a replay feeds the server the input of a recorded session instead, anything else is
+    // recorded if the debug config asks for it
+    let recording_config = debug_config.input_recording;
+    let mut replay = recording_config
+        .replay
+        .and_then(|path| match InputReplay::load(&path) {
+            Ok(replay) => Some(replay),
+            Err(error) => {
+                eprintln!("input replay {path}: {error}");
+                None
+            }
+        });
+    let mut recorder = recording_config
+        .record
+        .filter(|_| replay.is_none())
+        .and_then(|path| match InputRecorder::new(path.clone()) {
+            Ok(recorder) => Some(recorder),
+            Err(error) => {
+                eprintln!("input recording {path}: {error}");
+                None
+            }
+        });
+
+    // bots come up with the same input messages a client sends, without the network in between.
+    // Replays have their input recorded.
+    if replay.is_none() {
+        let bots = Rc::new(RefCell::new(Bots::spawn(debug_config.bots)));
+        // anyone can spawn and despawn them in debug builds
+        messages::BotCount::subscribe({
+            let bots = bots.clone();
+            move |_, msg| {
+                if cfg!(debug_assertions) {
+                    bots.borrow_mut().set_count(msg.count as usize);
+                }
+            }
+        });
+        let inbox = inbox.clone();
+        ambient_api::messages::Frame::subscribe(move |_| {
+            let mut inbox = inbox.borrow_mut();
+            for bot_input in bots.borrow_mut().update(frametime()) {
+                inbox.push((bot_input.player_id, ReceivedInput::Input(bot_input.input)));
+                if let Some(action) = bot_input.action {
+                    inbox.push((bot_input.player_id, ReceivedInput::Action(action)));
+                }
+            }
+        });
+    }
+
+    // seconds the server has been simulating, stamped on the replicated transforms
+    let mut server_time = 0.0;
+    // where every player was over the last ticks, punches hit where the attacker saw them
+    let mut history = TransformHistory::default();
     query((
         player(),
         player_input_direction(),
-        player_mouse_delta_x(),
-        player_mouse_delta_y(),
+        player_look(),
         is_dashing(),
         is_punching(),
         is_jumping(),
     ))
     .each_frame(move |players| {
-        for (
-            player_id,
-            (_, input_direction, mouse_delta_x, mouse_delta_y, is_dashing, is_punching, is_jumping),
-        ) in players
+        let player_ids: Vec<EntityId> = players.iter().map(|(id, _)| *id).collect();
+        let received = std::mem::take(&mut *inbox.borrow_mut());
+        let (dt, inputs) = match replay.as_mut().map(InputReplay::step) {
+            // the players joining next in the replay aren't set up yet
+            Some(ReplayStep::Waiting) => return,
+            Some(ReplayStep::Tick { dt, inputs }) => (dt, inputs),
+            Some(ReplayStep::Finished { ticks, divergences }) => {
+                replay = None;
+                report_replay(ticks, &divergences);
+                (frametime(), received)
+            }
+            None => (frametime(), received),
+        };
+        if let Some(error) = recorder
+            .as_mut()
+            .and_then(|recorder| recorder.record_tick(dt, &player_ids, &inputs).err())
         {
-            // apply input messages and update player rotation and position
-
-            //update player rotation
-            entity::mutate_component(player_id, rotation(), |q: &mut Quat| {
-                *q *= Quat::from_rotation_z(mouse_delta_x * 0.01);
-            });
+            // a recording missing ticks can't be replayed, it stops at the last complete one
+            let path = recorder.take().map(|recorder| recorder.path().to_string());
+            eprintln!(
+                "input recording {}: {error}, stopped recording",
+                path.unwrap_or_default()
+            );
+        }
+        for (player_id, input) in &inputs {
+            match input {
+                ReceivedInput::Input(msg) => receive_input(*player_id, msg),
+                ReceivedInput::Action(msg) => receive_input_action(*player_id, msg),
+            }
+        }
+        server_time += dt;
 
-            // update camera rotation on vertical access
-            // entity::mutate_component(player_id, view_vertical_rotation(), |q: &mut Quat| {
-            //     *q *= Quat::from_rotation_y(mouse_delta_y * 0.01);
-            // });
+        for (player_id, _) in players {
+            // the input was just applied, newer than what the query read
+            let input_direction =
+                entity::get_component(player_id, player_input_direction()).unwrap_or_default();
+            let look = entity::get_component(player_id, player_look()).unwrap_or_default();
+            let is_dashing = entity::get_component(player_id, is_dashing()).unwrap_or_default();
+            let punch_requested =
+                entity::get_component(player_id, is_punching()).unwrap_or_default();
+            let jump_requested = entity::get_component(player_id, is_jumping()).unwrap_or_default();
+            let health = Health(player_id);
+            if health.update_knockout(dt) {
+                respawn(player_id, spawn_policy);
+            }
+            let validator = InputValidator(player_id);
+            validator.update(dt);
 
-            entity::mutate_component(
+            // apply input messages and update player rotation and position
+            let input = MovementInput {
+                direction: input_direction,
+                is_dashing,
+                is_jumping: jump_requested,
+                // turning faster than a player can stays in `player_look` for the next frames
+                look: validator.turn(look, dt),
+            };
+            let was_grounded = entity::get_component(player_id, is_grounded()).unwrap_or_default();
+            let mut movement = MovementState {
+                rotation: entity::get_component(player_id, rotation()).unwrap(),
+                vertical_rotation_angle: entity::get_component(
+                    player_id,
+                    player_vertical_rotation_angle(),
+                )
+                .unwrap(),
+                vertical_velocity: entity::get_component(player_id, player_vertical_velocity())
+                    .unwrap_or_default(),
+                is_grounded: was_grounded,
+            };
+            movement.turn(input.look);
+            entity::set_component(player_id, rotation(), movement.rotation);
+            entity::set_component(
                 player_id,
                 player_vertical_rotation_angle(),
-                |angle: &mut f32| {
-                    *angle += mouse_delta_y * 0.01;
-                },
+                movement.vertical_rotation_angle,
             );
-
-            // Clamp the vertical rotation angle between a min and max value
-            let min_angle = -FRAC_PI_2 + 0.1; // Adjust the 0.1 value to avoid gimbal lock
-            let max_angle = FRAC_PI_2 - 0.1; // Adjust the 0.1 value to avoid gimbal lock
-            let clamped_angle = entity::get_component(player_id, player_vertical_rotation_angle())
-                .unwrap()
-                .clamp(min_angle, max_angle);
-
-            // Update the player_lookat_rotation quaternion
-            let clamped_vertical_rotation = Quat::from_rotation_y(clamped_angle);
             entity::set_component(
                 player_id,
                 view_vertical_rotation(),
-                clamped_vertical_rotation,
+                movement.view_vertical_rotation(),
             );
 
-            let player_rotation = entity::get_component(player_id, rotation()).unwrap();
-            let player_forward = player_rotation * world_front;
-            let player_right = player_rotation * world_right;
-            let mut speed = 0.1;
-            let mut player_direction: Vec3 = Vec3::ZERO;
+            let player_forward = movement.forward();
             let player_mesh_id = entity::get_component(player_id, player_mesh_ref()).unwrap();
             let mut animation_controller = PlayerAnimationController(player_mesh_id);
 
-            if is_jumping {
-                animation_controller.transition(PlayerAnimationEvent::Jump);
-            } else if is_punching {
-                animation_controller.transition(PlayerAnimationEvent::Punch);
-            } else {
-                if input_direction.x == 0.0 && input_direction.y == 0.0 {
-                    animation_controller.transition(PlayerAnimationEvent::Stop);
-                } else if is_dashing {
-                    speed = 0.8;
-                    animation_controller.transition(PlayerAnimationEvent::Dash);
+            // punching is resolved on the server, the client only requests it
+            if punch_requested {
+                entity::set_component(player_id, is_punching(), false);
+            }
+            let punch = Punch(player_id);
+            let was_punching = punch.is_punching();
+            let punch_phase = punch.update(punch_requested, dt);
+            if punch_phase == Some(PunchPhase::Active) {
+                let rewind = entity::get_component(player_id, punch_rewind()).unwrap_or_default();
+                punch.resolve_hits(player_forward, &player_ids, &history, server_time - rewind);
+            }
+
+            // the jump request is latched by the input handler, consume it here
+            if jump_requested {
+                entity::set_component(player_id, is_jumping(), false);
+            }
+            let jump_height = entity::get_component(player_id, player_jump_height())
+                .unwrap_or(DEFAULT_JUMP_HEIGHT);
+            let player_direction = movement.move_direction(&input);
+            // the punch only takes the upper body, the mech keeps walking but can't dash
+            let velocity = movement.velocity(&input, punch_phase.is_none(), jump_height, dt);
+
+            // move through the character controller so the mech collides with the ground and
+            // other players
+            let knockback =
+                entity::get_component(player_id, knockback_velocity()).unwrap_or_default();
+            entity::set_component(
+                player_id,
+                knockback_velocity(),
+                decay_knockback(knockback, dt),
+            );
+            let previous_position = entity::get_component(player_id, translation()).unwrap();
+            let collision =
+                physics::move_character(player_id, (velocity + knockback) * dt, 0.01, dt);
+            movement.collide(collision.down, collision.up);
+            let grounded = movement.is_grounded;
+            let vertical_velocity = movement.vertical_velocity;
+            entity::set_component(player_id, player_vertical_velocity(), vertical_velocity);
+            entity::set_component(player_id, is_grounded(), grounded);
+
+            // the client predicting this player replays its inputs after the acknowledged one on
+            // top of the state replicated together with it. It overwrites the transform with its
+            // prediction, so the transform gets mirrored where the client leaves it alone.
+            entity::set_component(
+                player_id,
+                player_input_ack(),
+                InputSequence(player_id).last(),
+            );
+            entity::set_component(
+                player_id,
+                authoritative_translation(),
+                entity::get_component(player_id, translation()).unwrap(),
+            );
+            entity::set_component(player_id, authoritative_rotation(), movement.rotation);
+            entity::set_component(
+                player_id,
+                authoritative_vertical_rotation_angle(),
+                movement.vertical_rotation_angle,
+            );
+            entity::set_component(player_id, player_snapshot_time(), server_time);
+
+            // drive the animation from the resulting physics state
+            if !grounded {
+                if vertical_velocity > 0.0 {
+                    animation_controller.transition(PlayerAnimationEvent::Jump);
                 } else {
-                    animation_controller.transition(PlayerAnimationEvent::Walk);
+                    animation_controller.transition(PlayerAnimationEvent::Fall);
                 }
+            } else if !was_grounded {
+                animation_controller.transition(PlayerAnimationEvent::Land);
+            } else if player_direction == Vec3::ZERO {
+                animation_controller.transition(PlayerAnimationEvent::Stop);
+            } else if is_dashing {
+                animation_controller.transition(PlayerAnimationEvent::Dash);
+            } else {
+                animation_controller.transition(PlayerAnimationEvent::Walk);
+            }
+            // the action layer plays the punch on top of whatever the legs are doing
+            if punch_phase.is_some() && !was_punching {
+                animation_controller.transition(PlayerAnimationEvent::Punch);
+            }
 
-                if input_direction.x == 1.0 {
-                    player_direction += player_forward;
-                }
-                if input_direction.x == -1.0 {
-                    player_direction += -player_forward;
-                }
-                if input_direction.y == -1.0 {
-                    player_direction += -player_right;
-                }
-                if input_direction.y == 1.0 {
-                    player_direction += player_right;
+            // feed the speed the mech actually moved at into the locomotion blend space, so it
+            // slows down when walking into something
+            let moved =
+                entity::get_component(player_id, translation()).unwrap() - previous_position;
+            let horizontal_speed = if dt > 0.0 {
+                moved.truncate().length() / dt
+            } else {
+                0.0
+            };
+            let input_total = input_direction.x.abs() + input_direction.y.abs();
+            let strafe = if input_total > 0.0 {
+                input_direction.y / input_total
+            } else {
+                0.0
+            };
+            animation_controller.set_locomotion(horizontal_speed, strafe);
+            // the punch animation decides when the fist is out, the hits get resolved from the
+            // next frame on
+            for notify in animation_controller.update(dt) {
+                match notify {
+                    "hit_active" => punch.set_hitbox_active(true),
+                    "hit_inactive" => punch.set_hitbox_active(false),
+                    _ => {}
                 }
             }
 
-            // update player translation
-            entity::mutate_component(player_id, translation(), |t| *t += player_direction * speed);
-
             // update player text
             let player_position = entity::get_component(player_id, translation()).unwrap();
+            if player_position.z < KILL_HEIGHT {
+                health.kill();
+            }
+
             let player_text_container =
                 entity::get_component(player_id, player_text_container_ref()).unwrap();
             entity::set_component(
@@ -221,9 +614,80 @@ pub fn main() {
                 translation(),
                 player_position + Vec3::Z * 9.,
             );
-
-            //TODO how to update physics here?
-            //physics::move_character(player_id, displace, 0.01, frametime());
         }
+        history.record(server_time, &player_ids);
     });
 }
+
+/// Logs how a replay went and tells the clients watching it
+fn report_replay(ticks: usize, divergences: &[ReplayDivergence]) {
+    eprintln!(
+        "input replay finished after {ticks} ticks, diverging {} times",
+        divergences.len()
+    );
+    for divergence in divergences {
+        eprintln!("input replay: {divergence}");
+    }
+    let msg = messages::InputReplayFinished::new(
+        ticks as u32,
+        divergences.iter().map(ToString::to_string).collect(),
+    );
+    msg.send_local_broadcast(false);
+    msg.send_client_broadcast_reliable();
+}
+
+/// Applies an input message of a player. They arrive unreliably and repeat the last few input
+/// commands, oldest first. Nothing in them is trusted, the validator clamps whatever a client
+/// couldn't have sent.
+fn receive_input(player_id: EntityId, msg: &messages::Input) {
+    let validator = InputValidator(player_id);
+    if !validator.allow_message() {
+        return;
+    }
+    let count = msg.input_directions.len();
+    if count == 0
+        || count > MAX_INPUT_COMMANDS
+        || msg.is_dashing.len() != count
+        || msg.looks.len() != count
+    {
+        validator.report(InputViolation::Malformed);
+        return;
+    }
+    let new_commands = InputSequence(player_id).receive(msg.sequence, count as u32) as usize;
+    if new_commands == 0 || Health(player_id).is_knocked_out() {
+        return;
+    }
+
+    // the newest command holds the current state, but every new one adds to the look so
+    // messages lost in between don't lose any turning
+    let look: Vec2 = msg.looks[count - new_commands..]
+        .iter()
+        .map(|&look| validator.look(look))
+        .sum();
+    entity::mutate_component(player_id, player_look(), |pending| *pending += look);
+    let direction = validator.direction(msg.input_directions[count - 1]);
+    let dashing = validator.dash(msg.is_dashing[count - 1], direction);
+    entity::set_component(player_id, is_dashing(), dashing);
+    entity::set_component(player_id, player_input_direction(), direction);
+}
+
+fn receive_input_action(player_id: EntityId, msg: &messages::InputAction) {
+    if !InputValidator(player_id).allow_message() || Health(player_id).is_knocked_out() {
+        return;
+    }
+    // jumps and punches are one-shot requests, keep them latched until the movement system
+    // consumes them so a following input message can't drop them
+    if msg.is_jumping {
+        entity::set_component(player_id, is_jumping(), true);
+    }
+    if msg.is_punching {
+        entity::set_component(player_id, is_punching(), true);
+        // the punch in progress keeps the moment it was aimed at
+        if !Punch(player_id).is_punching() {
+            let server_time =
+                entity::get_component(player_id, player_snapshot_time()).unwrap_or_default();
+            let rewind = rewind_for(msg.view_time, server_time);
+            entity::set_component(player_id, punch_rewind(), rewind);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use serde::Deserialize;
+
+use crate::{
+    components::{
+        dash_cooldown, is_dashing, is_grounded, is_jumping, is_punching, knockback_velocity,
+        player_dash_requested, player_input_direction, player_look, player_mesh_ref,
+        player_vertical_rotation_angle, player_vertical_velocity, punch_cooldown, punch_elapsed,
+        punch_hit_targets, punch_hitbox_active, punch_rewind, spawn_point, spawn_point_uses,
+        view_vertical_rotation,
+    },
+    health::Health,
+    player_animation_controller::PlayerAnimationController,
+};
+use ambient_api::{
+    components::core::{
+        player::player,
+        transform::{rotation, translation},
+    },
+    concepts::make_transformable,
+    prelude::*,
+};
+
+// spawn points are laid out in a ring around the center of the ground plane
+const SPAWN_POINT_COUNT: usize = 8;
+const SPAWN_RING_RADIUS: f32 = 40.0; // world units
+const SPAWN_HEIGHT: f32 = 2.0; // world units above the ground
+
+/// Spawns the spawn point entities, each facing the center of the map
+pub fn make_spawn_points() {
+    for i in 0..SPAWN_POINT_COUNT {
+        let angle = i as f32 / SPAWN_POINT_COUNT as f32 * std::f32::consts::TAU;
+        let position =
+            vec3(angle.cos(), angle.sin(), 0.) * SPAWN_RING_RADIUS + Vec3::Z * SPAWN_HEIGHT;
+        Entity::new()
+            .with_merge(make_transformable())
+            .with_default(spawn_point())
+            .with_default(spawn_point_uses())
+            .with(translation(), position)
+            .with(
+                rotation(),
+                Quat::from_rotation_z(angle + std::f32::consts::PI),
+            )
+            .spawn();
+    }
+}
+
+/// How a player being (re)spawned picks a spawn point
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpawnPolicy {
+    /// Cycle through the spawn points, picking the least used one
+    RoundRobin,
+    /// Pick the spawn point farthest away from the closest other player, the least used one
+    /// while nobody else is around
+    #[default]
+    FarthestFromEnemies,
+}
+
+/// The `[spawn]` section of the debug config
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpawnConfig {
+    pub policy: SpawnPolicy,
+}
+
+/// A spawn point as the spawn policies see it
+#[derive(Debug, Clone, Copy)]
+struct SpawnCandidate {
+    position: Vec3,
+    /// how many times players spawned at it
+    uses: u32,
+}
+
+/// Index of the candidate `policy` picks, with the other players that aren't knocked out at
+/// `enemy_positions`. `None` without any candidates.
+fn pick_spawn_point(
+    policy: SpawnPolicy,
+    candidates: &[SpawnCandidate],
+    enemy_positions: &[Vec3],
+) -> Option<usize> {
+    // the first of the least used ones, so spawning goes round the points in order
+    let least_used = || (0..candidates.len()).min_by_key(|&index| candidates[index].uses);
+    if policy == SpawnPolicy::RoundRobin || enemy_positions.is_empty() {
+        return least_used();
+    }
+
+    let closest_enemy_distance = |index: usize| {
+        enemy_positions
+            .iter()
+            .map(|enemy| enemy.distance(candidates[index].position))
+            .fold(f32::INFINITY, f32::min)
+    };
+    (0..candidates.len())
+        .max_by(|&a, &b| closest_enemy_distance(a).total_cmp(&closest_enemy_distance(b)))
+}
+
+fn select_spawn_point(player_id: EntityId, policy: SpawnPolicy) -> Option<EntityId> {
+    let spawn_points = entity::get_all(spawn_point());
+    let candidates: Vec<SpawnCandidate> = spawn_points
+        .iter()
+        .map(|&id| SpawnCandidate {
+            position: entity::get_component(id, translation()).unwrap_or_default(),
+            uses: entity::get_component(id, spawn_point_uses()).unwrap_or_default(),
+        })
+        .collect();
+    let enemy_positions: Vec<Vec3> = entity::get_all(player())
+        .into_iter()
+        .filter(|&id| id != player_id && !Health(id).is_knocked_out())
+        .filter_map(|id| entity::get_component(id, translation()))
+        .collect();
+    pick_spawn_point(policy, &candidates, &enemy_positions).map(|index| spawn_points[index])
+}
+
+/// Moves the player to a spawn point picked by `policy` and resets the movement, look, combat and
+/// animation state to what a freshly spawned player has.
+pub fn respawn(player_id: EntityId, policy: SpawnPolicy) {
+    let (position, facing) = match select_spawn_point(player_id, policy) {
+        Some(spawn_point_id) => {
+            entity::mutate_component(spawn_point_id, spawn_point_uses(), |uses| *uses += 1);
+            (
+                entity::get_component(spawn_point_id, translation()).unwrap(),
+                entity::get_component(spawn_point_id, rotation()).unwrap(),
+            )
+        }
+        None => (Vec3::Z * SPAWN_HEIGHT, Quat::IDENTITY),
+    };
+
+    physics::set_character_position(player_id, position);
+    entity::set_component(player_id, translation(), position);
+    entity::set_component(player_id, rotation(), facing);
+    entity::set_component(player_id, view_vertical_rotation(), Quat::IDENTITY);
+    entity::set_component(player_id, player_vertical_rotation_angle(), 0.0);
+
+    entity::set_component(player_id, player_vertical_velocity(), 0.0);
+    entity::set_component(player_id, knockback_velocity(), Vec3::ZERO);
+    entity::set_component(player_id, is_grounded(), false);
+
+    entity::set_component(player_id, player_input_direction(), Vec2::ZERO);
+    entity::set_component(player_id, player_look(), Vec2::ZERO);
+    entity::set_component(player_id, is_dashing(), false);
+    entity::set_component(player_id, player_dash_requested(), false);
+    entity::set_component(player_id, dash_cooldown(), 0.0);
+    entity::set_component(player_id, is_jumping(), false);
+    entity::set_component(player_id, is_punching(), false);
+
+    entity::remove_component(player_id, punch_elapsed());
+    entity::remove_component(player_id, punch_hitbox_active());
+    entity::remove_component(player_id, punch_hit_targets());
+    entity::set_component(player_id, punch_cooldown(), 0.0);
+    entity::set_component(player_id, punch_rewind(), 0.0);
+
+    Health(player_id).revive();
+
+    if let Some(player_mesh_id) = entity::get_component(player_id, player_mesh_ref()) {
+        PlayerAnimationController(player_mesh_id).reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spawn points on the x axis at `xs`, used as often as `uses`
+    fn candidates(xs: &[f32], uses: &[u32]) -> Vec<SpawnCandidate> {
+        xs.iter()
+            .zip(uses)
+            .map(|(&x, &uses)| SpawnCandidate {
+                position: vec3(x, 0.0, SPAWN_HEIGHT),
+                uses,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_takes_the_least_used_spawn_point() {
+        let points = candidates(&[0.0, 10.0, 20.0], &[2, 1, 1]);
+        // the first of the least used, wherever the others are
+        let enemies = [vec3(10.0, 0.0, 0.0)];
+        assert_eq!(
+            pick_spawn_point(SpawnPolicy::RoundRobin, &points, &enemies),
+            Some(1)
+        );
+
+        // spawning over and over goes round every point
+        let mut points = candidates(&[0.0, 10.0, 20.0], &[0, 0, 0]);
+        let mut picked = vec![];
+        for _ in 0..6 {
+            let index = pick_spawn_point(SpawnPolicy::RoundRobin, &points, &[]).unwrap();
+            points[index].uses += 1;
+            picked.push(index);
+        }
+        assert_eq!(picked, [0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn farthest_from_enemies_avoids_the_closest_enemy() {
+        let points = candidates(&[0.0, 10.0, 20.0, 30.0], &[0, 0, 0, 5]);
+        let cases: &[(&[Vec3], usize)] = &[
+            (&[vec3(0.0, 0.0, 0.0)], 3),
+            (&[vec3(30.0, 0.0, 0.0)], 0),
+            // away from both, even if it's the most used
+            (&[vec3(5.0, 0.0, 0.0), vec3(22.0, 0.0, 0.0)], 3),
+            (&[vec3(-5.0, 0.0, 0.0), vec3(26.0, 0.0, 0.0)], 1),
+        ];
+        for &(enemies, expected) in cases {
+            assert_eq!(
+                pick_spawn_point(SpawnPolicy::FarthestFromEnemies, &points, enemies),
+                Some(expected),
+                "{enemies:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn farthest_from_enemies_falls_back_to_the_least_used_without_enemies() {
+        let points = candidates(&[0.0, 10.0, 20.0], &[1, 0, 0]);
+        assert_eq!(
+            pick_spawn_point(SpawnPolicy::FarthestFromEnemies, &points, &[]),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn no_spawn_points_picks_nothing() {
+        for policy in [SpawnPolicy::RoundRobin, SpawnPolicy::FarthestFromEnemies] {
+            assert_eq!(pick_spawn_point(policy, &[], &[Vec3::ZERO]), None);
+        }
+    }
+
+    #[test]
+    fn spawn_policy_parses_from_the_debug_config() {
+        let config: SpawnConfig = toml::from_str("policy = \"round_robin\"").unwrap();
+        assert_eq!(config.policy, SpawnPolicy::RoundRobin);
+        let config: SpawnConfig = toml::from_str("").unwrap();
+        assert_eq!(config.policy, SpawnPolicy::FarthestFromEnemies);
+        assert!(toml::from_str::<SpawnConfig>("policy = \"random\"").is_err());
+    }
+}